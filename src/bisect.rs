@@ -0,0 +1,185 @@
+// Copyright 2018 The Rust Project Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Bisecting nightlies or CI builds with a closure that decides whether a
+//! toolchain has the regression, for tools that install and test toolchains
+//! their own way instead of through the command line.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::{Date, Duration, Utc};
+use failure::Error;
+
+use git;
+use least_satisfying::{least_satisfying, Satisfies};
+
+/// A toolchain a bisection chooses from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Toolchain {
+    /// The nightly published on this date.
+    Nightly(Date<Utc>),
+    /// The CI build of this bors merge.
+    Ci(git::Commit),
+}
+
+impl fmt::Display for Toolchain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Toolchain::Nightly(date) => write!(f, "nightly-{}", date.format("%Y-%m-%d")),
+            Toolchain::Ci(ref commit) => write!(f, "{}", commit.sha),
+        }
+    }
+}
+
+/// The range to bisect, both ends included.
+#[derive(Clone, Debug)]
+pub enum Bounds {
+    Nightlies { start: Date<Utc>, end: Date<Utc> },
+    Commits { start: String, end: String },
+}
+
+/// What to bisect.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub bounds: Bounds,
+    /// The rust-lang/rust branch whose commits are bisected, usually `master`.
+    pub branch: String,
+}
+
+/// Where a bisection found the regression.
+#[derive(Clone, Debug)]
+pub struct BisectionResult {
+    /// The toolchains that were chosen from, oldest first.
+    pub searched: Vec<Toolchain>,
+    /// The index in `searched` of the first toolchain with the regression.
+    pub found: usize,
+}
+
+impl BisectionResult {
+    /// The first toolchain with the regression.
+    pub fn regressed(&self) -> &Toolchain {
+        &self.searched[self.found]
+    }
+}
+
+/// Bisects the range in `config`, calling `predicate` to find out whether a
+/// toolchain has the regression.
+pub fn bisect<P>(config: &Config, predicate: P) -> Result<BisectionResult, Error>
+where
+    P: FnMut(&Toolchain) -> Satisfies,
+{
+    match config.bounds {
+        Bounds::Nightlies { start, end } => bisect_nightlies(start, end, predicate),
+        Bounds::Commits { ref start, ref end } => bisect_ci(start, end, &config.branch, predicate),
+    }
+}
+
+/// Bisects the nightlies from `start` to `end`. Not every day has a nightly,
+/// so `predicate` should answer `Unknown` for the ones it can't install.
+pub fn bisect_nightlies<P>(start: Date<Utc>, end: Date<Utc>, predicate: P) -> Result<BisectionResult, Error>
+where
+    P: FnMut(&Toolchain) -> Satisfies,
+{
+    let mut toolchains = Vec::new();
+    let mut date = start;
+    while date <= end {
+        toolchains.push(Toolchain::Nightly(date));
+        date = date + Duration::days(1);
+    }
+    search(toolchains, predicate)
+}
+
+/// Bisects the CI builds of the bors merges from `start` to `end` on
+/// `branch`, as listed by `git::get_commits_between`. CI artifacts are only
+/// kept for a while, so `predicate` should answer `Unknown` for the ones it
+/// can't install.
+pub fn bisect_ci<P>(start: &str, end: &str, branch: &str, predicate: P) -> Result<BisectionResult, Error>
+where
+    P: FnMut(&Toolchain) -> Satisfies,
+{
+    let commits = git::get_commits_between(start, end, branch)?;
+    search(commits.into_iter().map(Toolchain::Ci).collect(), predicate)
+}
+
+/// Checks that `toolchains` start without the regression and end with it,
+/// then finds where it begins, testing each toolchain at most once.
+fn search<P>(toolchains: Vec<Toolchain>, mut predicate: P) -> Result<BisectionResult, Error>
+where
+    P: FnMut(&Toolchain) -> Satisfies,
+{
+    if toolchains.len() < 2 {
+        bail!("a bisection needs at least two toolchains, found {}", toolchains.len());
+    }
+    let found = {
+        let mut known = HashMap::new();
+        let mut test = |i: usize| *known.entry(i).or_insert_with(|| predicate(&toolchains[i]));
+
+        let last = toolchains.len() - 1;
+        match test(0) {
+            Satisfies::No => {}
+            Satisfies::Yes => bail!(
+                "the start of the range, {}, already has the regression",
+                toolchains[0]
+            ),
+            Satisfies::Unknown => bail!("could not test the start of the range, {}", toolchains[0]),
+        }
+        match test(last) {
+            Satisfies::Yes => {}
+            Satisfies::No => bail!(
+                "the end of the range, {}, doesn't have the regression",
+                toolchains[last]
+            ),
+            Satisfies::Unknown => bail!("could not test the end of the range, {}", toolchains[last]),
+        }
+
+        let indices = (0..toolchains.len()).collect::<Vec<_>>();
+        least_satisfying(&indices, |&i| test(i))
+    };
+    Ok(BisectionResult {
+        searched: toolchains,
+        found,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bisect_nightlies, Toolchain};
+    use chrono::{Date, NaiveDate, Utc};
+    use least_satisfying::Satisfies;
+
+    fn date(day: u32) -> Date<Utc> {
+        Date::from_utc(NaiveDate::from_ymd(2019, 11, day), Utc)
+    }
+
+    #[test]
+    fn bisects_nightlies_with_a_closure() {
+        let mut tested = Vec::new();
+        let result = bisect_nightlies(date(1), date(20), |t| {
+            tested.push(t.clone());
+            match *t {
+                Toolchain::Nightly(d) if d == date(13) => Satisfies::Unknown,
+                Toolchain::Nightly(d) if d >= date(12) => Satisfies::Yes,
+                _ => Satisfies::No,
+            }
+        })
+        .unwrap();
+        assert_eq!(*result.regressed(), Toolchain::Nightly(date(12)));
+        assert_eq!(result.searched.len(), 20);
+        // each toolchain is only tested once
+        for t in &tested {
+            assert_eq!(tested.iter().filter(|other| *other == t).count(), 1);
+        }
+    }
+
+    #[test]
+    fn rejects_bounds_that_dont_bracket_the_regression() {
+        assert!(bisect_nightlies(date(1), date(5), |_| Satisfies::Yes).is_err());
+        assert!(bisect_nightlies(date(1), date(5), |_| Satisfies::No).is_err());
+        assert!(bisect_nightlies(date(5), date(5), |_| Satisfies::No).is_err());
+    }
+}
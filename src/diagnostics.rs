@@ -0,0 +1,160 @@
+// Copyright 2018 The Rust Project Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Match on the structured diagnostics of `--message-format=json` builds
+
+use std::str::FromStr;
+
+use failure::Error;
+use serde_json::{self, Value};
+
+/// Selects diagnostics by one of their structured fields, which are far more
+/// stable across compiler versions than the wording of the message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiagnosticFilter {
+    /// An error code such as `E0277`.
+    Code(String),
+    /// A level such as `error` or `warning`.
+    Level(String),
+    /// A diagnostic with a span in a file whose path ends with this.
+    File(String),
+}
+
+impl FromStr for DiagnosticFilter {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<DiagnosticFilter, Error> {
+        let filter = if s.starts_with("level=") {
+            DiagnosticFilter::Level(s["level=".len()..].to_string())
+        } else if s.starts_with("file=") {
+            DiagnosticFilter::File(s["file=".len()..].to_string())
+        } else if s.starts_with("code=") {
+            DiagnosticFilter::Code(s["code=".len()..].to_string())
+        } else {
+            DiagnosticFilter::Code(s.to_string())
+        };
+        match filter {
+            DiagnosticFilter::Code(ref v) | DiagnosticFilter::Level(ref v) | DiagnosticFilter::File(ref v)
+                if v.is_empty() =>
+            {
+                bail!("empty diagnostic filter `{}`", s)
+            }
+            _ => {}
+        }
+        Ok(filter)
+    }
+}
+
+impl DiagnosticFilter {
+    fn matches(&self, diagnostic: &Value) -> bool {
+        match *self {
+            DiagnosticFilter::Code(ref code) => {
+                diagnostic["code"]["code"].as_str() == Some(code.as_str())
+            }
+            DiagnosticFilter::Level(ref level) => {
+                diagnostic["level"].as_str() == Some(level.as_str())
+            }
+            DiagnosticFilter::File(ref file) => match diagnostic["spans"].as_array() {
+                Some(spans) => spans.iter().any(|span| {
+                    span["file_name"]
+                        .as_str()
+                        .map_or(false, |name| name.ends_with(file.as_str()))
+                }),
+                None => false,
+            },
+        }
+    }
+
+    /// A best-effort match against human-readable output, for toolchains that
+    /// can't produce JSON diagnostics.
+    fn matches_text(&self, text: &str) -> bool {
+        match *self {
+            DiagnosticFilter::Code(ref code) => text.contains(&format!("[{}]", code)),
+            DiagnosticFilter::Level(ref level) => text
+                .lines()
+                .any(|line| line.starts_with(&format!("{}:", level)) || line.starts_with(&format!("{}[", level))),
+            DiagnosticFilter::File(ref file) => text.contains(&format!("--> {}", file)),
+        }
+    }
+}
+
+/// Extracts the rustc diagnostics from the given output, whether they were
+/// wrapped by cargo's `--message-format=json` or printed directly by
+/// `rustc --error-format=json`. Returns `None` when the output contains no
+/// JSON messages at all.
+fn diagnostics(output: &str) -> Option<Vec<Value>> {
+    let mut saw_json = false;
+    let mut diagnostics = Vec::new();
+    for line in output.lines() {
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        if !message.is_object() {
+            continue;
+        }
+        saw_json = true;
+        if message["reason"] == "compiler-message" {
+            diagnostics.push(message["message"].clone());
+        } else if message["level"].is_string() && message["message"].is_string() {
+            diagnostics.push(message);
+        }
+    }
+    if saw_json {
+        Some(diagnostics)
+    } else {
+        None
+    }
+}
+
+/// Whether the output contains a diagnostic matching `filter`. If neither
+/// stream has JSON messages, for example because the toolchain predates them,
+/// this falls back to searching the plain text.
+pub fn output_has_diagnostic(filter: &DiagnosticFilter, stdout: &str, stderr: &str) -> bool {
+    let mut found_json = false;
+    for stream in &[stdout, stderr] {
+        if let Some(diagnostics) = diagnostics(stream) {
+            found_json = true;
+            if diagnostics.iter().any(|d| filter.matches(d)) {
+                return true;
+            }
+        }
+    }
+    if found_json {
+        return false;
+    }
+
+    eprintln!("no JSON diagnostics found in the output; falling back to a text search");
+    filter.matches_text(stdout) || filter.matches_text(stderr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{output_has_diagnostic, DiagnosticFilter};
+
+    const CARGO_OUTPUT: &str = r#"{"reason":"compiler-message","package_id":"foo 0.1.0","message":{"message":"the trait bound `u8: Foo` is not satisfied","code":{"code":"E0277","explanation":null},"level":"error","spans":[{"file_name":"src/main.rs","line_start":4}],"children":[],"rendered":"error[E0277]: ..."}}
+{"reason":"build-finished","success":false}"#;
+
+    #[test]
+    fn matches_cargo_json() {
+        let code: DiagnosticFilter = "E0277".parse().unwrap();
+        let level: DiagnosticFilter = "level=error".parse().unwrap();
+        let file: DiagnosticFilter = "file=main.rs".parse().unwrap();
+        let other: DiagnosticFilter = "E0308".parse().unwrap();
+        assert!(output_has_diagnostic(&code, CARGO_OUTPUT, ""));
+        assert!(output_has_diagnostic(&level, CARGO_OUTPUT, ""));
+        assert!(output_has_diagnostic(&file, CARGO_OUTPUT, ""));
+        assert!(!output_has_diagnostic(&other, CARGO_OUTPUT, ""));
+    }
+
+    #[test]
+    fn falls_back_to_text() {
+        let code: DiagnosticFilter = "E0277".parse().unwrap();
+        let stderr = "error[E0277]: the trait bound `u8: Foo` is not satisfied\n --> src/main.rs:4:5";
+        assert!(output_has_diagnostic(&code, "", stderr));
+        assert!(!output_has_diagnostic(&code, "", "error: aborting"));
+    }
+}
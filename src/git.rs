@@ -15,13 +15,15 @@ use std::path::Path;
 use chrono::{DateTime, TimeZone, Utc};
 use failure::Error;
 use git2::build::RepoBuilder;
-use git2::{Commit as Git2Commit, Repository};
+use git2::{Commit as Git2Commit, Repository, Sort};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Commit {
     pub sha: String,
     pub date: DateTime<Utc>,
     pub summary: String,
+    /// The whole commit message, e.g. with the pull requests of a rollup.
+    pub message: String,
 }
 
 impl Commit {
@@ -31,6 +33,7 @@ impl Commit {
             sha: commit.id().to_string(),
             date: Utc.timestamp(commit.time().seconds(), 0),
             summary: String::from_utf8_lossy(commit.summary_bytes().unwrap()).to_string(),
+            message: String::from_utf8_lossy(commit.message_bytes()).to_string(),
         }
     }
 }
@@ -42,19 +45,16 @@ fn lookup_rev<'rev>(repo: &'rev Repository, rev: &str) -> Result<Git2Commit<'rev
     bail!("Could not find a commit for revision specifier '{}'", rev)
 }
 
-fn get_repo() -> Result<Repository, Error> {
+/// Opens (or clones) the rust repository, fetching `branch` so that
+/// `origin/<branch>` is up to date.
+fn get_repo(branch: &str) -> Result<Repository, Error> {
     let loc = Path::new("rust.git");
     match (RUST_SRC_REPO, loc.exists()) {
         (Some(_), _) | (_, true) => {
             let path = RUST_SRC_REPO.map(Path::new).unwrap_or(loc);
             eprintln!("opening existing repository at {:?}", path);
             let repo = Repository::open(path)?;
-            {
-                eprintln!("refreshing repository");
-                let mut remote = repo.find_remote("origin")
-                    .or_else(|_| repo.remote_anonymous("origin"))?;
-                remote.fetch(&["master"], None, None)?;
-            }
+            refresh(&repo, branch)?;
             Ok(repo)
         }
         (None, false) => {
@@ -66,20 +66,89 @@ fn get_repo() -> Result<Repository, Error> {
     }
 }
 
-pub fn expand_commit(sha: &str) -> Result<String, Error> {
-    let repo = get_repo()?;
+/// Fetches `branch` from origin so that `origin/<branch>` and recent commits
+/// can be resolved. A failed fetch, e.g. without network access, only warns:
+/// the existing checkout may well be recent enough.
+fn refresh(repo: &Repository, branch: &str) -> Result<(), Error> {
+    let tracking = format!("refs/remotes/origin/{}", branch);
+    let before = repo.refname_to_id(&tracking).ok();
+
+    eprintln!("refreshing repository");
+    let mut remote = repo
+        .find_remote("origin")
+        .or_else(|_| repo.remote_anonymous("origin"))?;
+    if let Err(err) = remote.fetch(&[branch], None, None) {
+        eprintln!(
+            "warning: failed to fetch {} ({}); using the repository as it is, \
+             so recent commits may be missing",
+            branch,
+            err.message()
+        );
+        return Ok(());
+    }
+
+    match (before, repo.refname_to_id(&tracking).ok()) {
+        (Some(before), Some(after)) if before == after => {
+            eprintln!("origin/{} is up to date at {}", branch, after)
+        }
+        (Some(before), Some(after)) => {
+            eprintln!("origin/{} updated from {} to {}", branch, before, after)
+        }
+        (None, Some(after)) => eprintln!("origin/{} is now at {}", branch, after),
+        (_, None) => {}
+    }
+    Ok(())
+}
+
+pub fn expand_commit(sha: &str, branch: &str) -> Result<String, Error> {
+    let repo = get_repo(branch)?;
     let rev = lookup_rev(&repo, sha)?;
     Ok(rev.id().to_string())
 }
 
+/// Returns the bors merge commit `count` merges before `last_commit`, following
+/// the history of `branch`.
+pub fn commit_before(last_commit: &str, count: usize, branch: &str) -> Result<String, Error> {
+    let repo = get_repo(branch)?;
+    let mut current = lookup_rev(&repo, last_commit)?;
+    for _ in 0..count {
+        let mut parent = match current.parents().next() {
+            Some(parent) => parent,
+            None => bail!("{} has fewer than {} commits before it", last_commit, count),
+        };
+        // like `get_commits_between`, step over commits that aren't bors merges
+        while parent.author().name() != Some("bors") {
+            parent = match parent.parents().next() {
+                Some(grandparent) => grandparent,
+                None => bail!("{} has fewer than {} commits before it", last_commit, count),
+            };
+        }
+        current = parent;
+    }
+    Ok(current.id().to_string())
+}
+
 /// Returns the bors merge commits between the two specified boundaries
-/// (boundaries inclusive).
-pub fn get_commits_between(first_commit: &str, last_commit: &str) -> Result<Vec<Commit>, Error> {
-    let repo = get_repo()?;
+/// (boundaries inclusive), following the history of `branch`.
+pub fn get_commits_between(
+    first_commit: &str,
+    last_commit: &str,
+    branch: &str,
+) -> Result<Vec<Commit>, Error> {
+    let repo = get_repo(branch)?;
     eprintln!("looking up first commit");
     let mut first = lookup_rev(&repo, first_commit)?;
     eprintln!("looking up second commit");
     let last = lookup_rev(&repo, last_commit)?;
+    if first.id() == last.id() {
+        bail!(
+            "start ({}) and end ({}) are the same commit {}; nothing to bisect. \
+             Try widening the range.",
+            first_commit,
+            last_commit,
+            first.id()
+        );
+    }
 
     // Sanity check -- our algorithm below only works reliably if the
     // two commits are merge commits made by bors
@@ -134,3 +203,44 @@ pub fn get_commits_between(first_commit: &str, last_commit: &str) -> Result<Vec<
     );
     Ok(res)
 }
+
+/// Returns the first-parent history of a local checkout from `first_commit`
+/// to `last_commit` (both inclusive), in chronological order. Unlike
+/// `get_commits_between` this doesn't require bors merges, so it also works
+/// for unmerged branches.
+pub fn get_local_commits_between(
+    checkout: &Path,
+    first_commit: &str,
+    last_commit: &str,
+) -> Result<Vec<Commit>, Error> {
+    let repo = Repository::open(checkout)?;
+    let mut first = lookup_rev(&repo, first_commit)?;
+    let last = lookup_rev(&repo, last_commit)?;
+    if first.id() == last.id() {
+        bail!(
+            "start ({}) and end ({}) are the same commit {}; nothing to bisect. \
+             Try widening the range.",
+            first_commit,
+            last_commit,
+            first.id()
+        );
+    }
+
+    let mut walk = repo.revwalk()?;
+    walk.push(last.id())?;
+    walk.hide(first.id())?;
+    walk.simplify_first_parent();
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE);
+
+    let mut res = vec![Commit::from_git2_commit(&mut first)];
+    for id in walk {
+        let mut commit = repo.find_commit(id?)?;
+        res.push(Commit::from_git2_commit(&mut commit));
+    }
+    eprintln!(
+        "found {} commits in the specified range of {}",
+        res.len(),
+        checkout.display()
+    );
+    Ok(res)
+}
@@ -8,18 +8,71 @@
 use std::collections::BTreeMap;
 use std::fmt;
 
-pub fn least_satisfying<T, P>(slice: &[T], mut predicate: P) -> usize
+/// Finds the first `Yes` in a slice that starts with a `No` and ends with a
+/// `Yes`, by binary search.
+///
+/// When a probe yields `Unknown`, its neighbours are probed outwards until a
+/// known result is found on either side; the unknown run in between is then
+/// stepped around, and if the change from `No` to `Yes` is only straddled by
+/// unknowns, the first known `Yes` after them is returned. Callers that would
+/// rather not spend these extra probes can map `Unknown` to `No` or `Yes`
+/// in `predicate` instead.
+pub fn least_satisfying<T, P>(slice: &[T], predicate: P) -> usize
 where
     T: fmt::Display + fmt::Debug,
     P: FnMut(&T) -> Satisfies,
+{
+    least_satisfying_with_progress(slice, predicate, |_| {})
+}
+
+/// Where a bisection by `least_satisfying_with_progress` stands, as passed
+/// to its `report` callback right before a step of the binary search tests
+/// an element.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// The step about to be taken, counting from 1. Steps whose element was
+    /// already tested, e.g. while stepping around unknowns, aren't counted.
+    pub step: usize,
+    /// About how many steps the whole search takes, going by what is left.
+    pub estimated_steps: usize,
+    /// How many elements are left between the last known `No` and the first
+    /// known `Yes`.
+    pub remaining: usize,
+}
+
+/// Like `least_satisfying`, but calls `report` before each step that tests
+/// an element, for the caller to show how far along the search is.
+pub fn least_satisfying_with_progress<T, P, R>(slice: &[T], mut predicate: P, mut report: R) -> usize
+where
+    T: fmt::Display + fmt::Debug,
+    P: FnMut(&T) -> Satisfies,
+    R: FnMut(Progress),
 {
     let mut cache = BTreeMap::new();
-    let mut predicate = |idx: usize| *cache.entry(idx).or_insert_with(|| predicate(&slice[idx]));
+    let mut step = 0;
+    // `window` is the span between the known `No` and `Yes` when testing
+    // `idx` is a step of the binary search, to be reported
+    let mut test = |idx: usize, window: Option<usize>| {
+        if let Some(&r) = cache.get(&idx) {
+            return r;
+        }
+        if let Some(window) = window {
+            step += 1;
+            report(Progress {
+                step,
+                estimated_steps: step - 1 + steps_left(window),
+                remaining: window - 1,
+            });
+        }
+        let r = predicate(&slice[idx]);
+        cache.insert(idx, r);
+        r
+    };
     let mut unknown_ranges: Vec<(usize, usize)> = Vec::new();
     let mut rm_no = 0; // presume that the slice starts with a no
 
     eprintln!("verifying the start of the range does not reproduce the regression");
-    match predicate(rm_no) {
+    match test(rm_no, None) {
         Satisfies::No => {
             eprintln!("confirmed the start of the range does not reproduce the regression")
         }
@@ -29,7 +82,7 @@ where
     let mut lm_yes = slice.len() - 1; // presume that the slice ends with a yes
 
     eprintln!("verifying the end of the range reproduces the regression");
-    match predicate(lm_yes) {
+    match test(lm_yes, None) {
         Satisfies::Yes => eprintln!("confirmed the end of the range reproduces the regression"),
         _ => panic!("the end of the range to test must reproduce the regression"),
     }
@@ -57,7 +110,7 @@ where
             }
         }
 
-        let r = predicate(next);
+        let r = test(next, Some(lm_yes - rm_no));
         match r {
             Satisfies::Yes => {
                 lm_yes = next;
@@ -69,11 +122,11 @@ where
             }
             Satisfies::Unknown => {
                 let mut left = next;
-                while left > 0 && predicate(left) == Satisfies::Unknown {
+                while left > 0 && test(left, None) == Satisfies::Unknown {
                     left -= 1;
                 }
                 let mut right = next;
-                while right + 1 < slice.len() && predicate(right) == Satisfies::Unknown {
+                while right + 1 < slice.len() && test(right, None) == Satisfies::Unknown {
                     right += 1;
                 }
                 unknown_ranges.push((left + 1, right - 1));
@@ -83,10 +136,112 @@ where
     }
 }
 
+/// How many more probes a binary search needs to pin down the first `Yes`
+/// among `window` candidates, i.e. `ceil(log2(window))`.
+fn steps_left(window: usize) -> usize {
+    let mut steps = 0;
+    while (1 << steps) < window {
+        steps += 1;
+    }
+    steps
+}
+
+/// A change in outcome between two elements, with only unknown elements (if
+/// any) between them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Transition {
+    pub before: usize,
+    pub after: usize,
+    pub from: Satisfies,
+    pub to: Satisfies,
+}
+
+/// Finds every point where the outcome changes, in both directions, rather
+/// than assuming a single change from `No` to `Yes` like `least_satisfying`.
+///
+/// The slice is split recursively, and a sub-range is only searched further
+/// when its ends differ or it spans more than `granularity` elements, so a
+/// regression that appears and is fixed again within fewer elements than
+/// that can go unnoticed. Unknown elements are skipped over.
+pub fn all_transitions<T, P>(slice: &[T], granularity: usize, mut predicate: P) -> Vec<Transition>
+where
+    T: fmt::Display + fmt::Debug,
+    P: FnMut(&T) -> Satisfies,
+{
+    let mut cache = BTreeMap::new();
+    let mut predicate = |idx: usize| *cache.entry(idx).or_insert_with(|| predicate(&slice[idx]));
+    let mut transitions = Vec::new();
+
+    if slice.len() < 2 {
+        return transitions;
+    }
+    let (first, last) = (0, slice.len() - 1);
+    let lo = if predicate(first) == Satisfies::Unknown {
+        known_between(&mut predicate, first, first + 1, last + 1)
+    } else {
+        Some(first)
+    };
+    let hi = match lo {
+        Some(lo) if predicate(last) == Satisfies::Unknown => known_between(&mut predicate, lo, last - 1, last),
+        Some(_) => Some(last),
+        None => None,
+    };
+    let mut ranges = match (lo, hi) {
+        (Some(lo), Some(hi)) if lo < hi => vec![(lo, hi)],
+        _ => return transitions,
+    };
+
+    while let Some((lo, hi)) = ranges.pop() {
+        let (from, to) = (predicate(lo), predicate(hi));
+        if from == to && hi - lo <= granularity {
+            continue;
+        }
+        match known_between(&mut predicate, lo, (lo + hi) / 2, hi) {
+            Some(mid) if mid > lo => {
+                // pushed in reverse so the lower half is searched first
+                ranges.push((mid, hi));
+                ranges.push((lo, mid));
+            }
+            _ => {
+                if from != to {
+                    transitions.push(Transition {
+                        before: lo,
+                        after: hi,
+                        from,
+                        to,
+                    });
+                }
+            }
+        }
+    }
+
+    transitions
+}
+
+/// The element closest to `mid` whose outcome is known, starting at `mid` and
+/// staying below `hi` and above `lo`.
+fn known_between<P>(predicate: &mut P, lo: usize, mid: usize, hi: usize) -> Option<usize>
+where
+    P: FnMut(usize) -> Satisfies,
+{
+    for distance in 0..(hi - lo) {
+        if mid + distance < hi && predicate(mid + distance) != Satisfies::Unknown {
+            return Some(mid + distance);
+        }
+        if mid > lo + distance && predicate(mid - distance) != Satisfies::Unknown {
+            return Some(mid - distance);
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::Satisfies::*;
-    use super::{least_satisfying, Satisfies};
+    use super::{
+        all_transitions, least_satisfying, least_satisfying_with_progress, steps_left, Progress,
+        Satisfies, Transition,
+    };
     use quickcheck::{QuickCheck, TestResult};
 
     fn prop(xs: Vec<Option<bool>>) -> TestResult {
@@ -108,6 +263,53 @@ mod tests {
         TestResult::from_bool(res == exp)
     }
 
+    #[test]
+    fn reports_progress_before_each_step() {
+        let mut reported = Vec::new();
+        let res = least_satisfying_with_progress(
+            &[No, No, No, No, No, No, No, No, Yes],
+            |i| *i,
+            |progress| reported.push(progress),
+        );
+        assert_eq!(res, 8);
+        let progress = |step, remaining| Progress {
+            step,
+            estimated_steps: 3,
+            remaining,
+        };
+        assert_eq!(reported, vec![progress(1, 7), progress(2, 3), progress(3, 1)]);
+    }
+
+    #[test]
+    fn reports_only_steps_that_test() {
+        let slice = [No, Unknown, Unknown, Unknown, No, Yes, Yes];
+        let mut tested = 0;
+        let mut steps = 0;
+        let res = least_satisfying_with_progress(
+            &slice,
+            |i| {
+                tested += 1;
+                *i
+            },
+            |progress| {
+                steps += 1;
+                assert_eq!(progress.step, steps);
+            },
+        );
+        assert_eq!(res, 5);
+        // the two ends are tested without being steps
+        assert!(steps <= tested - 2);
+    }
+
+    #[test]
+    fn steps_left_is_log2() {
+        assert_eq!(steps_left(1), 0);
+        assert_eq!(steps_left(2), 1);
+        assert_eq!(steps_left(5), 3);
+        assert_eq!(steps_left(8), 3);
+        assert_eq!(steps_left(9), 4);
+    }
+
     #[test]
     fn least_satisfying_1() {
         assert_eq!(
@@ -160,6 +362,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn all_transitions_both_directions() {
+        let slice = [No, No, Yes, Yes, Yes, No, No, Yes];
+        let found = all_transitions(&slice, 1, |i| *i);
+        let pairs = found.iter().map(|t| (t.before, t.after)).collect::<Vec<_>>();
+        assert_eq!(pairs, vec![(1, 2), (4, 5), (6, 7)]);
+    }
+
+    #[test]
+    fn all_transitions_skips_unknowns() {
+        let slice = [Unknown, No, Unknown, Unknown, Yes, Unknown];
+        assert_eq!(
+            all_transitions(&slice, 1, |i| *i),
+            vec![Transition {
+                before: 1,
+                after: 4,
+                from: No,
+                to: Yes,
+            }]
+        );
+    }
+
     #[test]
     fn qc_prop() {
         QuickCheck::new().quickcheck(prop as fn(_) -> _);
@@ -0,0 +1,41 @@
+// Copyright 2018 The Rust Project Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! The parts of `cargo-bisect-rustc` that can be used without its command
+//! line, for tools that want to bisect in-process:
+//!
+//! * `bisect`, `bisect_nightlies` and `bisect_ci` find the nightly or CI
+//!   build a regression starts with, given a `Config` or the bounds, and a
+//!   closure deciding whether a `Toolchain` has the regression, so no test
+//!   command is needed;
+//! * `least_satisfying` and `all_transitions` search any ordered slice the
+//!   same way, and `least_satisfying_with_progress` reports each step of the
+//!   search to a callback;
+//! * `git` lists the bors merges of rust-lang/rust between two commits, the
+//!   commits that have CI artifacts.
+//!
+//! The closure installs and tests each toolchain however the caller likes;
+//! the downloading and installing done by the binary is built around its
+//! command line options and stays there.
+
+extern crate chrono;
+#[macro_use]
+extern crate failure;
+extern crate git2;
+#[macro_use]
+extern crate log;
+#[cfg(test)]
+extern crate quickcheck;
+
+pub mod bisect;
+pub mod git;
+pub mod least_satisfying;
+
+pub use bisect::{bisect, bisect_ci, bisect_nightlies, BisectionResult, Bounds, Config, Toolchain};
+pub use least_satisfying::{
+    all_transitions, least_satisfying, least_satisfying_with_progress, Progress, Satisfies, Transition,
+};
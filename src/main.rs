@@ -5,6 +5,8 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+extern crate atty;
+extern crate cargo_bisect_rustc;
 extern crate chrono;
 extern crate dialoguer;
 extern crate dirs;
@@ -13,37 +15,47 @@ extern crate env_logger;
 extern crate failure;
 extern crate flate2;
 extern crate git2;
+extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate pbr;
-#[cfg(test)]
-extern crate quickcheck;
 extern crate regex;
 extern crate reqwest;
 extern crate rustc_version;
+#[macro_use]
+extern crate serde_json;
+extern crate sha2;
 extern crate structopt;
 extern crate tar;
 extern crate tee;
 extern crate tempdir;
+#[cfg(windows)]
+extern crate winapi;
 extern crate xz2;
 
 use std::env;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
 use std::fmt;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{self, Command, Stdio};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
 
 use chrono::{Date, Duration, naive, Utc};
-use dialoguer::Select;
+use dialoguer::{Confirmation, Select};
 use failure::Error;
 use flate2::read::GzDecoder;
 use pbr::{ProgressBar, Units};
 use regex::Regex;
-use reqwest::header::CONTENT_LENGTH;
-use reqwest::{Client, Response};
+use reqwest::header::{CONTENT_LENGTH, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, RequestBuilder, Response};
 use rustc_version::Channel;
 use structopt::StructOpt;
 use tar::Archive;
@@ -59,17 +71,28 @@ use xz2::read::XzDecoder;
 /// however, it does limit the amount of commits somewhat.
 const EPOCH_COMMIT: &str = "927c55d86b0be44337f37cf5b0a76fb8ba86e06c";
 
+/// How many days CI artifacts are kept for, by default; see
+/// `--max-artifact-age`.
+const MAX_ARTIFACT_AGE: &str = "167";
+
 const NIGHTLY_SERVER: &str = "https://static.rust-lang.org/dist";
 const CI_SERVER: &str = "https://s3-us-west-1.amazonaws.com/rust-lang-ci2";
 
-mod git;
-mod least_satisfying;
-use least_satisfying::{least_satisfying, Satisfies};
+mod diagnostics;
+mod manifest_cache;
+mod output_cache;
+mod sha256;
+use cargo_bisect_rustc::git;
+use cargo_bisect_rustc::{all_transitions, least_satisfying_with_progress, Progress, Satisfies};
+use diagnostics::DiagnosticFilter;
 
-fn get_commits(start: &str, end: &str) -> Result<Vec<git::Commit>, Error> {
+fn get_commits(start: &str, end: &str, branch: &str) -> Result<Vec<git::Commit>, Error> {
     eprintln!("fetching commits from {} to {}", start, end);
-    let commits = git::get_commits_between(start, end)?;
-    assert_eq!(commits.first().expect("at least one commit").sha, git::expand_commit(start)?);
+    let commits = git::get_commits_between(start, end, branch)?;
+    assert_eq!(
+        commits.first().expect("at least one commit").sha,
+        git::expand_commit(start, branch)?
+    );
 
     Ok(commits)
 }
@@ -95,15 +118,71 @@ struct Opts {
     #[structopt(long = "host", help = "Host triple for the compiler", default_value = "unknown")]
     host: String,
 
-    #[structopt(long = "target", help = "Target platform to install for cross-compilation")]
+    #[structopt(
+        long = "target",
+        help = "Target platform to install for cross-compilation, or a comma-separated list \
+                of them to test each one",
+        long_help = "Target platform to install for cross-compilation. With a comma-separated \
+                     list of targets, the standard library is installed for all of them and \
+                     the test runs once per target (cargo gets `--target`, a --script gets \
+                     CARGO_BUILD_TARGET); the toolchain counts as regressed if it regressed \
+                     for any of them."
+    )]
     target: Option<String>,
 
     #[structopt(long = "preserve", help = "Preserve the downloaded artifacts")]
     preserve: bool,
 
+    #[structopt(
+        long = "preserve-toolchains",
+        help = "Preserve the downloaded artifacts, and link each toolchain into rustup \
+                under a readable name such as `bisector-nightly-2019-11-01` or \
+                `bisector-ci-<SHA12>`, for use with `rustup run` afterwards"
+    )]
+    preserve_toolchains: bool,
+
     #[structopt(long = "preserve-target", help = "Preserve the target directory used for builds")]
     preserve_target: bool,
 
+    #[structopt(
+        long = "clean-between-probes",
+        help = "Remove the target directory before testing each toolchain, \
+                including one passed to cargo via `--target-dir`",
+        conflicts_with = "preserve_target"
+    )]
+    clean_between_probes: bool,
+
+    #[structopt(
+        long = "fresh-test-dir",
+        help = "Test each toolchain in a fresh temporary copy of the test directory, so that \
+                files left behind by one run can't affect the next"
+    )]
+    fresh_test_dir: bool,
+
+    #[structopt(
+        long = "install-tmp-dir",
+        help = "Directory to download and extract toolchains in before installing them, \
+                instead of the rustup tmp directory",
+        parse(from_os_str)
+    )]
+    install_tmp_dir: Option<PathBuf>,
+
+    #[structopt(
+        long = "artifacts-dir",
+        help = "Directory to download and install toolchains in instead of the rustup \
+                directory, e.g. on a larger disk; they are linked into rustup from there",
+        parse(from_os_str),
+        conflicts_with = "install_tmp_dir"
+    )]
+    artifacts_dir: Option<PathBuf>,
+
+    #[structopt(
+        long = "install-jobs",
+        help = "Number of components of a toolchain to download and extract in parallel",
+        default_value = "1"
+    )]
+    install_jobs: usize,
+
     #[structopt(
         long = "with-cargo", help = "Download cargo, by default the installed cargo is used"
     )]
@@ -114,6 +193,166 @@ struct Opts {
     )]
     with_src: bool,
 
+    #[structopt(
+        long = "component",
+        help = "Also download this rustup component, e.g. rust-analysis or clippy-preview; \
+                can be given several times",
+        raw(number_of_values = "1")
+    )]
+    components: Vec<String>,
+
+    #[structopt(
+        long = "download-retries",
+        help = "How often to retry a download that failed with a connection or server error; \
+                missing artifacts are never retried",
+        default_value = "3"
+    )]
+    download_retries: u32,
+
+    #[structopt(
+        long = "max-retries-per-toolchain",
+        help = "How often to rerun a test that couldn't tell whether the toolchain regressed, \
+                e.g. because it ran out of memory, before counting it as unknown",
+        default_value = "0"
+    )]
+    max_retries_per_toolchain: u32,
+
+    #[structopt(
+        long = "download-cache",
+        help = "Directory to keep downloaded tarballs in, so that later runs can install the \
+                same toolchains without downloading them again \
+                [default: <cache dir>/cargo-bisect-rustc]",
+        parse(from_os_str)
+    )]
+    download_cache: Option<PathBuf>,
+
+    #[structopt(
+        long = "no-cache",
+        help = "Neither reuse nor keep downloaded tarballs",
+        conflicts_with = "download_cache"
+    )]
+    no_cache: bool,
+
+    #[structopt(
+        long = "tool",
+        help = "Bisect a tool shipped as a component: clippy, rustfmt, rls, miri or rust-analyzer",
+        long_help = "Bisect a tool shipped as a rustup component instead of rustc: clippy, \
+                     rustfmt, rls, miri or rust-analyzer. The component is downloaded with each \
+                     toolchain, and the test runs the tool (`cargo clippy`, `rustfmt`, `rls`, \
+                     `cargo miri` or `rust-analyzer`) in the test directory with the arguments \
+                     after `--`, unless a --script is given. Only nightlies and CI builds for \
+                     which the tool built successfully include it; the others count as unknown."
+    )]
+    tool: Option<String>,
+
+    #[structopt(
+        long = "min-free-space",
+        help = "Free disk space to keep before installing a toolchain, e.g. 5G or 500M",
+        long_help = "Free disk space to keep on the toolchains' filesystem before installing \
+                     a toolchain, e.g. 5G or 500M. When there is less, toolchains left behind \
+                     by earlier bisections are removed, oldest first (unless --preserve is \
+                     given), and if that isn't enough the bisection stops. Only checked on \
+                     unix, where the free space is read from `df`.",
+        parse(try_from_str = "parse_size")
+    )]
+    min_free_space: Option<u64>,
+
+    #[structopt(
+        long = "rerun-verbose",
+        help = "Once the regression is found, test it and the toolchain before it again \
+                verbosely and save the output in this directory",
+        long_help = "Once the regression is found, test the regressed toolchain and the one \
+                     before it again with verbose output (`cargo -vv`, or \
+                     CARGO_TERM_VERBOSE=true for a --script) and RUST_BACKTRACE=1, and save \
+                     their complete output in this directory.",
+        parse(from_os_str)
+    )]
+    rerun_verbose: Option<PathBuf>,
+
+    #[structopt(
+        long = "verify-regression",
+        help = "Once the regression is found, reinstall it and the toolchain before it \
+                and test both again in a fresh copy of the test directory, warning if \
+                the result doesn't hold up"
+    )]
+    verify_regression: bool,
+
+    #[structopt(
+        long = "timeout",
+        help = "Kill a test that runs for longer than this many seconds"
+    )]
+    timeout: Option<u64>,
+
+    #[structopt(
+        long = "timeout-outcome",
+        help = "How to count a test that timed out: regressed, baseline or unknown",
+        default_value = "regressed"
+    )]
+    timeout_outcome: TestOutcome,
+
+    #[structopt(
+        long = "script-timeout",
+        help = "Kill a --script that runs for longer than this many seconds; takes the place \
+                of --timeout for scripts"
+    )]
+    script_timeout: Option<u64>,
+
+    #[structopt(
+        long = "script-timeout-outcome",
+        help = "How to count a --script that hit --script-timeout: regressed, baseline or unknown",
+        default_value = "unknown"
+    )]
+    script_timeout_outcome: TestOutcome,
+
+    #[structopt(
+        long = "jobs",
+        help = "While searching for a good nightly, download up to this many nightlies at \
+                once, the ones likely to be tested next in the background",
+        default_value = "1"
+    )]
+    jobs: usize,
+
+    #[structopt(
+        long = "resume",
+        help = "Reuse the results of an interrupted run, kept in \
+                `.cargo-bisect-rustc-state.json` in the test directory"
+    )]
+    resume: bool,
+
+    #[structopt(
+        long = "server",
+        env = "CARGO_BISECT_SERVER",
+        help = "Base URL of the server nightlies and their manifests are downloaded from, \
+                e.g. a mirror of the official one, or a file:// URL of a local copy",
+        raw(default_value = "NIGHTLY_SERVER")
+    )]
+    server: String,
+
+    #[structopt(
+        long = "ci-server",
+        env = "CARGO_BISECT_CI_SERVER",
+        help = "Base URL of the server CI artifacts are downloaded from, or a file:// URL of \
+                a local copy",
+        raw(default_value = "CI_SERVER")
+    )]
+    ci_server: String,
+
+    #[structopt(
+        long = "max-artifact-age",
+        help = "Leave out CI commits older than this many days, whose artifacts have been \
+                deleted; the retention policy has changed over time, and may differ for \
+                alt builds or a --ci-server mirror",
+        raw(default_value = "MAX_ARTIFACT_AGE")
+    )]
+    max_artifact_age: i64,
+
+    #[structopt(
+        long = "proxy",
+        help = "Proxy to download through, e.g. http://proxy.example.com:3128; by default \
+                HTTPS_PROXY, HTTP_PROXY and ALL_PROXY are used"
+    )]
+    proxy: Option<String>,
+
     #[structopt(
         long = "test-dir",
         help = "Directory to test; this is where you usually run `cargo build`",
@@ -129,9 +368,32 @@ struct Opts {
     )]
     prompt: bool,
 
+    #[structopt(
+        long = "prompt-timeout",
+        help = "With --prompt, take --prompt-default unless Enter is pressed within this many \
+                seconds to answer (on unix)"
+    )]
+    prompt_timeout: Option<u64>,
+
+    #[structopt(
+        long = "prompt-default",
+        help = "With --prompt, the answer taken when stdin is closed or --prompt-timeout \
+                passes: regressed, baseline or unknown",
+        default_value = "unknown"
+    )]
+    prompt_default: TestOutcome,
+
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbosity: usize,
 
+    #[structopt(
+        long = "log-format",
+        help = "Format of the log enabled with RUST_LOG: human, or json for one object \
+                with the level, module and message per line",
+        default_value = "human"
+    )]
+    log_format: LogFormat,
+
     #[structopt(
         help = "Arguments to pass to cargo when running",
         raw(multiple = "true", last = "true"),
@@ -141,12 +403,19 @@ struct Opts {
 
     #[structopt(
         long = "start",
-        help = "the left-bound for the search; this point should *not* have the regression"
+        help = "the left-bound for the search; this point should *not* have the regression",
+        long_help = "the left-bound for the search; this point should *not* have the \
+                     regression. A date (YYYY-MM-DD, or relative: today, yesterday, \
+                     N.days.ago, N.weeks.ago) or a commit."
     )]
     start: Option<Bound>,
 
     #[structopt(
-        long = "end", help = "the right-bound for the search; this point should have the regression"
+        long = "end",
+        help = "the right-bound for the search; this point should have the regression",
+        long_help = "the right-bound for the search; this point should have the regression. \
+                     A date (YYYY-MM-DD, or relative: today, yesterday, N.days.ago, \
+                     N.weeks.ago) or a commit."
     )]
     end: Option<Bound>,
 
@@ -155,8 +424,47 @@ struct Opts {
     )]
     by_commit: bool,
 
-    #[structopt(long = "install", help = "install the given artifact")]
-    install: Option<Bound>,
+    #[structopt(
+        long = "nightlies-from-file",
+        help = "bisect only the nightlies listed in this file, one YYYY-MM-DD date per line",
+        parse(from_os_str),
+        raw(alias = r#""nightlies-file""#),
+        raw(conflicts_with_all = r#"&["start", "end", "by_commit"]"#)
+    )]
+    nightlies_from_file: Option<PathBuf>,
+
+    #[structopt(
+        long = "branch",
+        default_value = "master",
+        help = "the rust-lang/rust branch whose CI builds to bisect, e.g. `beta`"
+    )]
+    branch: String,
+
+    #[structopt(
+        long = "channel",
+        help = "Bisect the dated builds of this channel, nightly or beta; bisecting beta \
+                defaults --branch to beta",
+        default_value = "nightly"
+    )]
+    channel: ReleaseChannel,
+
+    #[structopt(
+        long = "install",
+        help = "install the given artifact; can be repeated, or given a comma-separated \
+                list, to install several",
+        raw(number_of_values = "1"),
+        raw(use_delimiter = "true")
+    )]
+    install: Vec<Bound>,
+
+    #[structopt(
+        long = "extract-only",
+        help = "With --install, only download and unpack the toolchain into a directory \
+                named after it in this directory, without registering it with rustup",
+        raw(requires = r#""install""#),
+        parse(from_os_str)
+    )]
+    extract_only: Option<PathBuf>,
 
     #[structopt(long = "force-install", help = "force installation over existing artifacts")]
     force_install: bool,
@@ -167,6 +475,359 @@ struct Opts {
         parse(from_os_str)
     )]
     script: Option<PathBuf>,
+
+    #[structopt(
+        long = "rustflags",
+        help = "Flags to build with, such as `-C opt-level=2`, passed in RUSTFLAGS (after any \
+                already set), or on the command line with --rustc-only",
+        raw(allow_hyphen_values = "true")
+    )]
+    rustflags: Option<String>,
+
+    #[structopt(
+        long = "rustc-only",
+        help = "Test by compiling this single source file with the toolchain's rustc, \
+                without cargo; arguments after `--` are passed to rustc",
+        raw(conflicts_with_all = r#"&["script", "tool", "offline_eval", "collect_timings"]"#),
+        parse(from_os_str)
+    )]
+    rustc_only: Option<PathBuf>,
+
+    #[structopt(
+        long = "setup-script",
+        help = "Script to run before each test, e.g. a first build for incremental bugs; \
+                may be given several times",
+        long_help = "Script to run before each test, in the test directory and with \
+                     RUSTUP_TOOLCHAIN set like --script. May be given several times to run \
+                     several steps in order; only the test itself (cargo or --script) decides \
+                     the outcome. If a setup script fails, the toolchain is treated as unknown.",
+        raw(number_of_values = "1"),
+        parse(from_os_str)
+    )]
+    setup_scripts: Vec<PathBuf>,
+
+    #[structopt(
+        long = "offline-eval",
+        help = "Fetch dependencies with `cargo fetch` before each test, then run the \
+                test itself with cargo's network access disabled"
+    )]
+    offline_eval: bool,
+
+    #[structopt(
+        long = "keep-failed-logs",
+        help = "Save the output of every regressed or unknown toolchain to a file in this directory",
+        parse(from_os_str)
+    )]
+    keep_failed_logs: Option<PathBuf>,
+
+    #[structopt(
+        long = "log-dir",
+        help = "Save the output of every toolchain tested, with the outcome it was given, \
+                to a file in this directory",
+        parse(from_os_str)
+    )]
+    log_dir: Option<PathBuf>,
+
+    #[structopt(
+        long = "collect-timings",
+        help = "Build with `-Z timings` and collect the reports in this directory, by toolchain",
+        long_help = "Build with `-Z timings` and collect the reports in this directory, by \
+                     toolchain. Useful together with a regress mode to see where build time \
+                     went. Only applies when bisect-rustc runs cargo itself, not to a --script.",
+        parse(from_os_str)
+    )]
+    collect_timings: Option<PathBuf>,
+
+    #[structopt(
+        long = "keep-going",
+        help = "If the --start toolchain already has the regression, look further back for \
+                one that doesn't instead of stopping"
+    )]
+    keep_going: bool,
+
+    #[structopt(
+        long = "since-last-good",
+        help = "Without a known good --start, look back from --end for a toolchain without \
+                the regression, going back ever further; nightlies do this anyway",
+        conflicts_with = "start"
+    )]
+    since_last_good: bool,
+
+    #[structopt(
+        long = "validate",
+        help = "Before bisecting, test the start and end toolchains this many times each and \
+                stop if their results vary, i.e. if the test is flaky",
+        raw(conflicts_with_all = r#"&["local_build", "nightlies_from_file"]"#)
+    )]
+    validate: Option<usize>,
+
+    #[structopt(
+        long = "estimate",
+        help = "Print a rough estimate of how long the bisection will take, then exit"
+    )]
+    estimate: bool,
+
+    #[structopt(
+        long = "estimate-test-secs",
+        help = "How long testing one toolchain is assumed to take for `--estimate`",
+        default_value = "60"
+    )]
+    estimate_test_secs: u64,
+
+    #[structopt(
+        long = "dry-run",
+        help = "Print the toolchains the bisection would choose from, without installing or \
+                testing anything, then exit",
+        raw(conflicts_with_all = r#"&["estimate", "install"]"#)
+    )]
+    dry_run: bool,
+
+    #[structopt(
+        long = "yes",
+        help = "Don't ask for confirmation before a bisection that downloads a lot"
+    )]
+    yes: bool,
+
+    #[structopt(
+        long = "summary-stats",
+        help = "Print statistics about the probes, downloads and time taken at the end"
+    )]
+    summary_stats: bool,
+
+    #[structopt(
+        long = "count",
+        help = "Print how many toolchains were downloaded and tested, taken from the cache, \
+                failed to install or came out unknown at the end"
+    )]
+    count: bool,
+
+    #[structopt(
+        long = "fail-on-ambiguous",
+        help = "Exit with status 2 unless the regression was pinned down to a single \
+                toolchain whose predecessor was confirmed to be good"
+    )]
+    fail_on_ambiguous: bool,
+
+    #[structopt(
+        long = "no-default-nightly-fallback",
+        help = "Without --end, end at the latest nightly on the server rather than the \
+                installed default nightly"
+    )]
+    no_default_nightly_fallback: bool,
+
+    #[structopt(
+        long = "all-transitions",
+        help = "Find every point where the outcome changes, not just the first regression",
+        long_help = "Find every point where the outcome changes, not just the first \
+                     regression. Useful when a range contains several regressions and fixes. \
+                     Ranges whose ends agree are only searched further if they span more than \
+                     --scan-granularity toolchains. Nightly bisections need a --start date."
+    )]
+    all_transitions: bool,
+
+    #[structopt(
+        long = "toolchain-link-name-prefix",
+        help = "Prefix for the names toolchains are installed under, e.g. `alice-`, to keep \
+                concurrent bisections apart",
+        default_value = ""
+    )]
+    toolchain_link_name_prefix: String,
+
+    #[structopt(
+        long = "interactive",
+        help = "Choose which toolchain to test at each step of the bisection, or stop early",
+        long_help = "Choose which toolchain to test at each step of the bisection: the \
+                     midpoint, another toolchain in the remaining range, or none, accepting \
+                     the current end of the range as the regression. Falls back to the \
+                     automated search when not run in a terminal."
+    )]
+    interactive: bool,
+
+    #[structopt(
+        long = "write-repro",
+        help = "Once the regression is found, write the command line, host and regressed \
+                toolchain to this file",
+        parse(from_os_str)
+    )]
+    write_repro: Option<PathBuf>,
+
+    #[structopt(
+        long = "copy-artifacts-to",
+        help = "Save the tarballs of the regressing toolchain in this directory",
+        long_help = "Save the tarballs of the regressing toolchain in this directory, so that \
+                     it can still be installed after the CI artifacts expire. They are \
+                     downloaded again once the regression is found, and take a few hundred \
+                     megabytes per toolchain (more with extra --target or --with-* components).",
+        parse(from_os_str)
+    )]
+    copy_artifacts_to: Option<PathBuf>,
+
+    #[structopt(
+        long = "local-build",
+        help = "Build each commit in this rust checkout instead of downloading CI artifacts",
+        long_help = "Build each commit in this rust checkout instead of downloading CI \
+                     artifacts, e.g. to bisect an unmerged branch. Needs commit --start and \
+                     --end. Each probed commit is checked out and built with --build-script \
+                     (by default `python x.py build --stage 1 src/libstd`), and the result \
+                     is kept in --build-cache so no commit is built twice. The checkout's \
+                     working tree is left at the last commit built.",
+        raw(conflicts_with_all = r#"&["nightlies_from_file", "alt", "all_transitions"]"#),
+        parse(from_os_str)
+    )]
+    local_build: Option<PathBuf>,
+
+    #[structopt(
+        long = "build-script",
+        help = "Script that builds the checked out commit with --local-build",
+        parse(from_os_str)
+    )]
+    build_script: Option<PathBuf>,
+
+    #[structopt(
+        long = "build-output",
+        help = "Where --local-build finds the built toolchain, relative to the checkout \
+                [default: build/<host>/stage1]",
+        parse(from_os_str)
+    )]
+    build_output: Option<PathBuf>,
+
+    #[structopt(
+        long = "build-cache",
+        help = "Where --local-build keeps the toolchain built for each commit",
+        default_value = "local-builds",
+        parse(from_os_str)
+    )]
+    build_cache: PathBuf,
+
+    #[structopt(
+        long = "exclude-commit",
+        help = "Leave out the CI build of this commit, or of the commits starting with this \
+                prefix, e.g. because its artifacts are broken; can be given several times",
+        raw(number_of_values = "1")
+    )]
+    exclude_commits: Vec<String>,
+
+    #[structopt(
+        long = "min-commit-interval",
+        help = "Only test every Nth CI commit, then offer to bisect the gap the regression \
+                was found in",
+        long_help = "Only test every Nth CI commit (always including the ends of the range), \
+                     trading precision for fewer downloads on long ranges. Once the coarse \
+                     search has found the gap the regression is in, you are asked whether to \
+                     bisect the commits in it as well; without a terminal the gap is reported \
+                     instead.",
+        default_value = "1"
+    )]
+    min_commit_interval: usize,
+
+    #[structopt(
+        long = "bisect-include-rollups",
+        help = "Whether to test the CI builds of rollups; with `false`, the commits between \
+                them are bisected first, and rollups are only tested if the regression \
+                is in one",
+        default_value = "true",
+        parse(try_from_str)
+    )]
+    include_rollups: bool,
+
+    #[structopt(
+        long = "since-commit-count",
+        help = "Start this many bors merges before the end commit (origin/<branch> by default)",
+        raw(conflicts_with_all = r#"&["start", "nightlies_from_file", "local_build"]"#)
+    )]
+    since_commit_count: Option<usize>,
+
+    #[structopt(
+        long = "output-dir",
+        help = "Directory for all files the run produces; logs go to `logs/` in it",
+        long_help = "Directory for all files the run produces, created if needed. The logs of \
+                     failed tests go to `logs/` in it unless --keep-failed-logs says otherwise, \
+                     and relative paths given to --keep-failed-logs, --log-dir, --collect-timings, \
+                     --output-cache and --copy-artifacts-to are taken relative to it.",
+        parse(from_os_str)
+    )]
+    output_dir: Option<PathBuf>,
+
+    #[structopt(
+        long = "scan-granularity",
+        help = "With --all-transitions, the longest range assumed unchanged when its ends agree",
+        default_value = "8"
+    )]
+    scan_granularity: usize,
+
+    #[structopt(
+        long = "classifier",
+        help = "Program that decides the outcome of each test run from its captured output",
+        long_help = "Program that decides the outcome of each test run from its captured \
+                     output, instead of `--regress`. It receives a JSON object on stdin: \
+                     {\"toolchain\": string, \"status\": exit code or null if killed by a \
+                     signal, \"success\": bool, \"stdout\": string, \"stderr\": string}. \
+                     If the first word it prints is `regressed`, `baseline` or `unknown`, \
+                     that is the outcome; otherwise exiting with 0 means baseline, 1 means \
+                     regressed and anything else means unknown.",
+        parse(from_os_str)
+    )]
+    classifier: Option<PathBuf>,
+
+    #[structopt(
+        long = "output-cache",
+        help = "Record the output of each test run in this directory and reuse it when the \
+                same toolchain is tested again with the same command",
+        long_help = "Record the output of each test run in this directory and reuse it when \
+                     the same toolchain is tested again with the same command. Rerunning with \
+                     another --regress mode or --classifier then only classifies the recorded \
+                     outputs again, without installing those toolchains. Records made with a \
+                     different test command (cargo arguments, --script or --setup-script) are \
+                     ignored.",
+        conflicts_with = "prompt",
+        parse(from_os_str)
+    )]
+    output_cache: Option<PathBuf>,
+
+    #[structopt(
+        long = "regress",
+        default_value = "error",
+        help = "Customize what is treated as regression",
+        long_help = "Customize what is treated as regression. Values include \
+                     `--regress=error`, `--regress=non-error`, `--regress=ice`, \
+                     `--regress=non-ice` and `--regress=success`; run with \
+                     `--list-regress-modes` for a description of each. Modes can be \
+                     combined with `&` (all of them) and `|` (any of them), where `&` \
+                     binds tighter, e.g. `--regress='ice&contains:foo'`."
+    )]
+    regress: OutputProcessingMode,
+
+    #[structopt(
+        long = "bisect-direction",
+        help = "What to look for: the first toolchain with the regression, or the first \
+                without it: regression or fix",
+        long_help = "What to look for: `regression`, the first toolchain whose test result \
+                     counts as regressed under --regress after a range that doesn't, or \
+                     `fix`, the first toolchain that no longer counts as regressed after a \
+                     range that does. With `fix`, --start must have the problem and --end must \
+                     not.",
+        default_value = "regression"
+    )]
+    bisect_direction: BisectDirection,
+
+    #[structopt(
+        long = "unknown",
+        help = "How the search treats a toolchain that can't be tested, e.g. because it \
+                fails to install: skip, treat-as-baseline or treat-as-regressed",
+        long_help = "How the search treats a toolchain that can't be tested, e.g. because \
+                     it fails to install or its test is inconclusive. `skip`, the default, \
+                     probes its neighbours instead and leaves it out; `treat-as-baseline` \
+                     and `treat-as-regressed` count it as that, which avoids the extra \
+                     probes when many toolchains in the range are broken, at the risk of \
+                     pinning the regression on one of them.",
+        default_value = "skip"
+    )]
+    unknown: UnknownHandling,
+
+    #[structopt(
+        long = "list-regress-modes", help = "List the supported `--regress` modes and exit"
+    )]
+    list_regress_modes: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -182,6 +843,9 @@ struct BoundParseError {}
 impl FromStr for Bound {
     type Err = BoundParseError;
     fn from_str(s: &str) -> Result<Bound, BoundParseError> {
+        if let Some(date) = relative_date(s, Utc::today()) {
+            return Ok(Bound::Date(date));
+        }
         match chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
             Ok(date) => Ok(Bound::Date(Date::from_utc(date, Utc))),
             Err(_) => Ok(Bound::Commit(s.to_string())),
@@ -189,39 +853,130 @@ impl FromStr for Bound {
     }
 }
 
-impl Bound {
-    fn as_commit(self) -> Result<Self, Error> {
-        match self {
-            Bound::Commit(commit) => Ok(Bound::Commit(commit)),
-            Bound::Date(date) => {
-                let date_str = date.format("%Y-%m-%d");
-                let url = format!("{}/{}/channel-rust-nightly-git-commit-hash.txt", NIGHTLY_SERVER, date_str);
-
-                eprintln!("fetching {}", url);
-                let client = Client::new();
-                let name = format!("nightly manifest {}", date_str);
-                let (response, mut bar) = download_progress(&client, &name, &url)?;
-                let mut response = TeeReader::new(response, &mut bar);
-                let mut commit = String::new();
-                response.read_to_string(&mut commit)?;
-
-                eprintln!("converted {} to {}", date_str, commit);
-
-                Ok(Bound::Commit(commit))
+/// Resolves `today`, `yesterday`, `N.days.ago` and `N.weeks.ago` against
+/// `today`.
+fn relative_date(s: &str, today: Date<Utc>) -> Option<Date<Utc>> {
+    let days_ago = match s {
+        "today" => 0,
+        "yesterday" => 1,
+        _ => {
+            let mut parts = s.split('.');
+            let count: u32 = parts.next()?.parse().ok()?;
+            let days = match parts.next()? {
+                "day" | "days" => i64::from(count),
+                "week" | "weeks" => i64::from(count) * 7,
+                _ => return None,
+            };
+            if parts.next()? != "ago" || parts.next().is_some() {
+                return None;
             }
+            days
         }
+    };
+    // far beyond any build, and small enough for `Duration::days`
+    if days_ago > 100 * 366 {
+        return None;
     }
+    today.checked_sub_signed(Duration::days(days_ago))
 }
 
-impl Opts {
-    fn emit_cargo_output(&self) -> bool {
-        self.verbosity >= 2
+#[test]
+fn test_relative_date() {
+    let date = |y, m, d| Date::from_utc(naive::NaiveDate::from_ymd(y, m, d), Utc);
+    let today = date(2019, 11, 15);
+    assert_eq!(relative_date("today", today), Some(today));
+    assert_eq!(relative_date("yesterday", today), Some(date(2019, 11, 14)));
+    assert_eq!(relative_date("30.days.ago", today), Some(date(2019, 10, 16)));
+    assert_eq!(relative_date("1.day.ago", today), Some(date(2019, 11, 14)));
+    assert_eq!(relative_date("2.weeks.ago", today), Some(date(2019, 11, 1)));
+    assert_eq!(relative_date("2.weeks", today), None);
+    assert_eq!(relative_date("2.months.ago", today), None);
+    assert_eq!(relative_date("1.days.ago.x", today), None);
+    assert_eq!(relative_date("-1.days.ago", today), None);
+    assert_eq!(relative_date("99999999999.weeks.ago", today), None);
+    assert_eq!(relative_date("2019-11-01", today), None);
+    assert_eq!(relative_date("a1b2c3d", today), None);
+
+    match "3.days.ago".parse() {
+        Ok(Bound::Date(d)) => assert_eq!(d, Utc::today() - Duration::days(3)),
+        other => panic!("unexpected {:?}", other),
+    }
+    match "2019-11-01".parse() {
+        Ok(Bound::Date(d)) => assert_eq!(d, date(2019, 11, 1)),
+        other => panic!("unexpected {:?}", other),
+    }
+    match "a1b2c3d".parse() {
+        Ok(Bound::Commit(ref commit)) => assert_eq!(commit, "a1b2c3d"),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bound::Commit(ref commit) => write!(f, "{}", commit),
+            Bound::Date(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+        }
+    }
+}
+
+impl Bound {
+    /// Resolves a date to the commit of that day's build of `channel`, as
+    /// published on `server`.
+    fn as_commit(
+        self,
+        client: &Client,
+        server: &str,
+        channel: ReleaseChannel,
+        retries: u32,
+        cache_dir: Option<&Path>,
+    ) -> Result<Self, Error> {
+        match self {
+            Bound::Commit(commit) => Ok(Bound::Commit(commit)),
+            Bound::Date(date) => {
+                let date_str = date.format("%Y-%m-%d");
+                let url = format!(
+                    "{}/{}/channel-rust-{}-git-commit-hash.txt",
+                    server, date_str, channel
+                );
+
+                eprintln!("fetching {}", url);
+                let commit = fetch_manifest(client, &url, retries, cache_dir)?;
+
+                eprintln!("converted {} to {}", date_str, commit);
+
+                Ok(Bound::Commit(commit))
+            }
+        }
+    }
+}
+
+impl Opts {
+    fn emit_cargo_output(&self) -> bool {
+        self.verbosity >= 2
+    }
+
+    /// Where downloaded tarballs and manifests are kept, if anywhere.
+    fn download_cache_dir(&self) -> Option<PathBuf> {
+        if self.no_cache {
+            return None;
+        }
+        match self.download_cache {
+            Some(ref dir) => Some(dir.clone()),
+            None => dirs::cache_dir().map(|dir| dir.join("cargo-bisect-rustc")),
+        }
     }
 }
 
 #[derive(Debug, Fail)]
 struct ExitError(i32);
 
+/// A test that was killed for running longer than `--timeout` or
+/// `--script-timeout`, with the outcome it counts as.
+#[derive(Debug, Fail)]
+#[fail(display = "timed out after {} seconds", _0)]
+struct TimedOut(u64, TestOutcome);
+
 impl fmt::Display for ExitError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "exiting with {}", self.0)
@@ -233,22 +988,89 @@ struct Toolchain {
     spec: ToolchainSpec,
     host: String,
     std_targets: Vec<String>,
+    /// Prepended to the name the toolchain is installed under.
+    name_prefix: String,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 enum ToolchainSpec {
-    Ci { commit: String, alt: bool },
+    /// A CI build; `channel` is the one in the names of its tarballs, beta
+    /// for commits of the beta branch.
+    Ci {
+        commit: String,
+        alt: bool,
+        channel: ReleaseChannel,
+    },
+    /// A commit built in a local checkout with `--local-build`; installed
+    /// under its own name so that it is never mistaken for the CI build.
+    Local { commit: String },
     Nightly { date: Date<Utc> },
+    Beta { date: Date<Utc> },
+}
+
+/// The channel whose dated builds are bisected, selected with `--channel`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ReleaseChannel {
+    Nightly,
+    Beta,
+}
+
+impl FromStr for ReleaseChannel {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<ReleaseChannel, Error> {
+        match s {
+            "nightly" => Ok(ReleaseChannel::Nightly),
+            "beta" => Ok(ReleaseChannel::Beta),
+            _ => bail!("expected nightly or beta, got `{}`", s),
+        }
+    }
+}
+
+impl fmt::Display for ReleaseChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReleaseChannel::Nightly => write!(f, "nightly"),
+            ReleaseChannel::Beta => write!(f, "beta"),
+        }
+    }
+}
+
+impl ToolchainSpec {
+    /// The build of `channel` published on `date`.
+    fn dated(channel: ReleaseChannel, date: Date<Utc>) -> ToolchainSpec {
+        match channel {
+            ReleaseChannel::Nightly => ToolchainSpec::Nightly { date },
+            ReleaseChannel::Beta => ToolchainSpec::Beta { date },
+        }
+    }
+
+    fn date(&self) -> Option<Date<Utc>> {
+        match *self {
+            ToolchainSpec::Ci { .. } | ToolchainSpec::Local { .. } => None,
+            ToolchainSpec::Nightly { date } | ToolchainSpec::Beta { date } => Some(date),
+        }
+    }
+
+    /// The channel in the names of the tarballs.
+    fn channel(&self) -> ReleaseChannel {
+        match *self {
+            ToolchainSpec::Ci { channel, .. } => channel,
+            ToolchainSpec::Local { .. } | ToolchainSpec::Nightly { .. } => ReleaseChannel::Nightly,
+            ToolchainSpec::Beta { .. } => ReleaseChannel::Beta,
+        }
+    }
 }
 
 impl fmt::Display for ToolchainSpec {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            ToolchainSpec::Ci { ref commit, alt } => {
+            ToolchainSpec::Ci { ref commit, alt, .. } => {
                 let alt_s = if alt { format!("-alt") } else { String::new() };
                 write!(f, "{}{}", commit, alt_s)
             }
+            ToolchainSpec::Local { ref commit } => write!(f, "local-{}", commit),
             ToolchainSpec::Nightly { ref date } => write!(f, "nightly-{}", date),
+            ToolchainSpec::Beta { ref date } => write!(f, "beta-{}", date),
         }
     }
 }
@@ -256,15 +1078,45 @@ impl fmt::Display for ToolchainSpec {
 impl Toolchain {
     fn rustup_name(&self) -> String {
         match self.spec {
-            ToolchainSpec::Ci { ref commit, alt } => {
+            ToolchainSpec::Ci { ref commit, alt, .. } => {
                 let alt_s = if alt { format!("-alt") } else { String::new() };
-                format!("ci-{}{}-{}", commit, alt_s, self.host)
+                format!("{}ci-{}{}-{}", self.name_prefix, commit, alt_s, self.host)
+            }
+            ToolchainSpec::Local { ref commit } => {
+                format!("{}local-{}-{}", self.name_prefix, commit, self.host)
             }
             // N.B. We need to call this with a nonstandard name so that rustup utilizes the
             // fallback cargo logic.
-            ToolchainSpec::Nightly { ref date } => {
-                format!("bisector-nightly-{}-{}", date.format("%Y-%m-%d"), self.host)
+            ToolchainSpec::Nightly { ref date } | ToolchainSpec::Beta { ref date } => {
+                format!(
+                    "{}bisector-{}-{}-{}",
+                    self.name_prefix,
+                    self.spec.channel(),
+                    date.format("%Y-%m-%d"),
+                    self.host
+                )
+            }
+        }
+    }
+
+    /// The name `--preserve-toolchains` links the toolchain under: without the
+    /// host or any prefix, and with the commit shortened, so that it is easy
+    /// to type after `rustup run`.
+    fn preserved_name(&self) -> String {
+        match self.spec {
+            ToolchainSpec::Ci { ref commit, alt, .. } => format!(
+                "bisector-ci-{}{}",
+                &commit[..cmp::min(12, commit.len())],
+                if alt { "-alt" } else { "" }
+            ),
+            ToolchainSpec::Local { ref commit } => {
+                format!("bisector-local-{}", &commit[..cmp::min(12, commit.len())])
             }
+            ToolchainSpec::Nightly { ref date } | ToolchainSpec::Beta { ref date } => format!(
+                "bisector-{}-{}",
+                self.spec.channel(),
+                date.format("%Y-%m-%d")
+            ),
         }
     }
 }
@@ -272,11 +1124,13 @@ impl Toolchain {
 impl fmt::Display for Toolchain {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.spec {
-            ToolchainSpec::Ci { ref commit, alt } => {
+            ToolchainSpec::Ci { ref commit, alt, .. } => {
                 let alt_s = if alt { format!("-alt") } else { String::new() };
                 write!(f, "{}{}", commit, alt_s)
             }
+            ToolchainSpec::Local { ref commit } => write!(f, "local-{}", commit),
             ToolchainSpec::Nightly { ref date } => write!(f, "nightly-{}", date.format("%Y-%m-%d")),
+            ToolchainSpec::Beta { ref date } => write!(f, "beta-{}", date.format("%Y-%m-%d")),
         }
     }
 }
@@ -286,16 +1140,95 @@ struct DownloadParams {
     url_prefix: String,
     tmp_dir: PathBuf,
     install_dir: PathBuf,
+    /// With `--artifacts-dir`, the rustup toolchains directory that installed
+    /// toolchains are linked into.
+    link_dir: Option<PathBuf>,
     install_cargo: bool,
     install_src: bool,
+    /// Extra components asked for with `--component`.
+    components: Vec<String>,
     force_install: bool,
+    jobs: usize,
+    /// How often a failed download is retried, see `download_progress`.
+    retries: u32,
+    /// Where downloaded tarballs are kept for later runs, unless `--no-cache`.
+    cache_dir: Option<PathBuf>,
+    tool: Option<&'static Tool>,
+    min_free_space: Option<u64>,
+    /// Whether toolchains of earlier runs may be removed to free space.
+    reclaim_space: bool,
+    /// Names of toolchains that freeing space must leave alone, such as the
+    /// ones being installed in the background.
+    in_use: Vec<String>,
+    /// With `--preserve-toolchains`, also link each toolchain into rustup
+    /// under its `preserved_name`.
+    preserve_names: bool,
+    /// Whether the server publishes `.sha256` files to check tarballs
+    /// against; the CI server doesn't.
+    checksums: bool,
+}
+
+/// How a single tarball is downloaded, see `download_verified`.
+#[derive(Clone, Copy, Debug)]
+struct DownloadPolicy {
+    retries: u32,
+    checksums: bool,
+}
+
+/// A tool shipped as a rustup component, for `--tool`.
+#[derive(Debug)]
+struct Tool {
+    name: &'static str,
+    /// The directory inside the component tarball holding its files.
+    subdir: &'static str,
+    /// How the tool is run, with `RUSTUP_TOOLCHAIN` selecting the toolchain.
+    command: &'static [&'static str],
+}
+
+const TOOLS: &[Tool] = &[
+    Tool {
+        name: "clippy",
+        subdir: "clippy-preview",
+        command: &["cargo", "clippy"],
+    },
+    Tool {
+        name: "rustfmt",
+        subdir: "rustfmt-preview",
+        command: &["rustfmt"],
+    },
+    Tool {
+        name: "rls",
+        subdir: "rls-preview",
+        command: &["rls"],
+    },
+    Tool {
+        name: "miri",
+        subdir: "miri-preview",
+        command: &["cargo", "miri"],
+    },
+    Tool {
+        name: "rust-analyzer",
+        subdir: "rust-analyzer-preview",
+        command: &["rust-analyzer"],
+    },
+];
+
+fn find_tool(name: &str) -> Result<&'static Tool, Error> {
+    match TOOLS.iter().find(|tool| tool.name == name) {
+        Some(tool) => Ok(tool),
+        None => bail!(
+            "unknown --tool `{}`, expected one of: {}",
+            name,
+            TOOLS.iter().map(|tool| tool.name).collect::<Vec<_>>().join(", ")
+        ),
+    }
 }
 
 impl DownloadParams {
     fn for_ci(cfg: &Config) -> Self {
         let url_prefix = format!(
             "{}/rustc-builds{}",
-            CI_SERVER,
+            cfg.args.ci_server,
             if cfg.args.alt { "-alt" } else { "" }
         );
 
@@ -303,20 +1236,49 @@ impl DownloadParams {
             url_prefix: url_prefix,
             tmp_dir: cfg.rustup_tmp_path.clone(),
             install_dir: cfg.toolchains_path.clone(),
+            link_dir: cfg.rustup_link_path.clone(),
             install_cargo: cfg.args.with_cargo,
             install_src: cfg.args.with_src,
+            components: cfg.args.components.clone(),
             force_install: cfg.args.force_install,
+            jobs: cfg.args.install_jobs,
+            retries: cfg.args.download_retries,
+            cache_dir: cfg.download_cache_dir(),
+            tool: cfg.tool,
+            min_free_space: cfg.args.min_free_space,
+            reclaim_space: !cfg.args.preserve,
+            in_use: Vec::new(),
+            preserve_names: cfg.args.preserve_toolchains,
+            checksums: false,
         }
     }
 
     fn for_nightly(cfg: &Config) -> Self {
         DownloadParams {
-            url_prefix: NIGHTLY_SERVER.to_string(),
+            url_prefix: cfg.args.server.clone(),
             tmp_dir: cfg.rustup_tmp_path.clone(),
             install_dir: cfg.toolchains_path.clone(),
+            link_dir: cfg.rustup_link_path.clone(),
             install_cargo: cfg.args.with_cargo,
             install_src: cfg.args.with_src,
+            components: cfg.args.components.clone(),
             force_install: cfg.args.force_install,
+            jobs: cfg.args.install_jobs,
+            retries: cfg.args.download_retries,
+            cache_dir: cfg.download_cache_dir(),
+            tool: cfg.tool,
+            min_free_space: cfg.args.min_free_space,
+            reclaim_space: !cfg.args.preserve,
+            in_use: Vec::new(),
+            preserve_names: cfg.args.preserve_toolchains,
+            checksums: true,
+        }
+    }
+
+    fn policy(&self) -> DownloadPolicy {
+        DownloadPolicy {
+            retries: self.retries,
+            checksums: self.checksums,
         }
     }
 }
@@ -327,6 +1289,8 @@ enum ArchiveError {
     Archive(#[cause] io::Error),
     #[fail(display = "Failed to create directory: {}", _0)]
     CreateDir(#[cause] io::Error),
+    #[fail(display = "Failed to move the unpacked files into place: {}", _0)]
+    Move(#[cause] io::Error),
 }
 
 #[derive(Fail, Debug)]
@@ -337,33 +1301,220 @@ enum DownloadError {
     Reqwest(#[cause] reqwest::Error),
     #[fail(display = "An archive error occurred: {}", _0)]
     Archive(#[cause] ArchiveError),
+    #[fail(display = "Could not use the download cache: {}", _0)]
+    Cache(#[cause] io::Error),
+    #[fail(display = "Could not read the download: {}", _0)]
+    Transfer(#[cause] io::Error),
+    #[fail(display = "Could not read a local artifact: {}", _0)]
+    Local(#[cause] io::Error),
+    #[fail(display = "Checksum mismatch for {}: expected {}, got {}", url, expected, actual)]
+    Checksum {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl DownloadError {
+    /// Whether this may come from the connection dropping halfway through a
+    /// download, which is worth downloading again.
+    fn is_interrupted_download(&self) -> bool {
+        match *self {
+            DownloadError::Reqwest(_)
+            | DownloadError::Transfer(_)
+            | DownloadError::Archive(ArchiveError::Archive(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Total number of bytes downloaded during this run.
+static DOWNLOADED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Passes reads through, adding their size to `DOWNLOADED_BYTES`.
+struct CountingReader<R> {
+    inner: R,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        DOWNLOADED_BYTES.fetch_add(n, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// The file a `file://` URL refers to, so that a `--server` or `--ci-server`
+/// can be a local mirror of the artifacts.
+fn local_path(url: &str) -> Option<PathBuf> {
+    let url = reqwest::Url::parse(url).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    url.to_file_path().ok()
+}
+
+#[test]
+fn test_local_path() {
+    assert_eq!(local_path("https://static.rust-lang.org/dist"), None);
+    if cfg!(unix) {
+        assert_eq!(
+            local_path("file:///mirror/dist/../manifests.txt"),
+            Some(PathBuf::from("/mirror/manifests.txt"))
+        );
+    }
+}
+
+/// How long to wait before retrying a download for the `attempt`th time:
+/// twice as long as before each time, up to about a minute.
+fn retry_delay(attempt: u32) -> std::time::Duration {
+    std::time::Duration::from_secs(1 << cmp::min(attempt, 6))
+}
+
+/// Sends `request` for `url`, retrying connection and server errors up to
+/// `retries` times and waiting twice as long before each attempt. Any other
+/// response, e.g. a missing file, is returned right away for the caller to
+/// look at.
+fn send_with_retries(request: RequestBuilder, url: &str, retries: u32) -> reqwest::Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let result = request
+            .try_clone()
+            .expect("requests without a body can be cloned")
+            .send()
+            .and_then(|response| {
+                if response.status().is_server_error() {
+                    response.error_for_status()
+                } else {
+                    Ok(response)
+                }
+            });
+        match result {
+            Err(ref err) if attempt < retries && !err.is_client_error() && !err.is_redirect() => {
+                let delay = retry_delay(attempt);
+                eprintln!(
+                    "warning: downloading {} failed ({}); retrying in {}s",
+                    url,
+                    err,
+                    delay.as_secs()
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
 }
 
+/// Starts downloading `url`, retrying as `send_with_retries` does; a missing
+/// file is reported right away. A `file://` URL is read from the disk.
 fn download_progress(
     client: &Client,
     name: &str,
     url: &str,
-) -> Result<(Response, ProgressBar<io::Stdout>), DownloadError> {
+    retries: u32,
+) -> Result<(CountingReader<Box<dyn Read>>, ProgressBar<io::Stdout>), DownloadError> {
     debug!("downloading <{}>...", url);
 
-    let response = client.get(url).send().map_err(DownloadError::Reqwest)?;
+    if let Some(path) = local_path(url) {
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(DownloadError::NotFound(url.to_string()));
+            }
+            Err(err) => return Err(DownloadError::Local(err)),
+        };
+        let length = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let mut bar = ProgressBar::new(length);
+        bar.set_units(Units::Bytes);
+        bar.message(&format!("{}: ", name));
+        return Ok((CountingReader { inner: Box::new(file) }, bar));
+    }
+
+    let response =
+        send_with_retries(client.get(url), url, retries).map_err(DownloadError::Reqwest)?;
 
     if response.status() == reqwest::StatusCode::NOT_FOUND {
         return Err(DownloadError::NotFound(url.to_string()));
     }
     let response = response.error_for_status().map_err(DownloadError::Reqwest)?;
 
-    let length = response
-        .headers()
-        .get(CONTENT_LENGTH)
-        .and_then(|c| c.to_str().ok())
-        .and_then(|c| c.parse().ok())
-        .unwrap_or(0);
+    let length = content_length(&response);
     let mut bar = ProgressBar::new(length);
     bar.set_units(Units::Bytes);
     bar.message(&format!("{}: ", name));
 
-    Ok((response, bar))
+    Ok((CountingReader { inner: Box::new(response) }, bar))
+}
+
+/// Fetches a small file from the release server, like the commit hash of a
+/// nightly. With a `cache_dir`, the copy kept there from an earlier run is
+/// only downloaded again if the server says it changed.
+fn fetch_manifest(
+    client: &Client,
+    url: &str,
+    retries: u32,
+    cache_dir: Option<&Path>,
+) -> Result<String, Error> {
+    if let Some(path) = local_path(url) {
+        return fs::read_to_string(&path)
+            .map(|text| text.trim_end().to_string())
+            .map_err(|err| format_err!("could not read {}: {}", path.display(), err));
+    }
+
+    let cached = cache_dir.and_then(|dir| manifest_cache::load(dir, url));
+
+    let mut request = client.get(url);
+    if let Some(ref cached) = cached {
+        if let Some(ref etag) = cached.etag {
+            request = request.header(IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(ref last_modified) = cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+        }
+    }
+    let mut response = send_with_retries(request, url, retries)?.error_for_status()?;
+
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        if let Some(cached) = cached {
+            debug!("{} is unchanged, using the cached copy", url);
+            return Ok(cached.body);
+        }
+    }
+
+    let header = |response: &Response, name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let etag = header(&response, ETAG);
+    let last_modified = header(&response, LAST_MODIFIED);
+    let body = response.text()?;
+    if let Some(dir) = cache_dir {
+        if etag.is_some() || last_modified.is_some() {
+            let fetched = manifest_cache::Cached {
+                body: body.clone(),
+                etag,
+                last_modified,
+            };
+            if let Err(err) = manifest_cache::store(dir, url, &fetched) {
+                eprintln!("warning: could not cache {}: {}", url, err);
+            }
+        }
+    }
+    Ok(body)
+}
+
+/// The announced size of a response, or 0 if the server didn't say.
+fn content_length(response: &Response) -> u64 {
+    response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|c| c.to_str().ok())
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(0)
 }
 
 fn download_tar_xz(
@@ -372,12 +1523,12 @@ fn download_tar_xz(
     url: &str,
     strip_prefix: Option<&Path>,
     dest: &Path,
+    policy: DownloadPolicy,
 ) -> Result<(), DownloadError> {
-    let (response, mut bar) = download_progress(client, name, url)?;
-    let response = TeeReader::new(response, &mut bar);
-    let response = XzDecoder::new(response);
-    unarchive(response, strip_prefix, dest).map_err(DownloadError::Archive)?;
-    Ok(())
+    let staged = download_verified(client, name, url, policy, |response| {
+        unarchive_staged(XzDecoder::new(response), strip_prefix, dest)
+    })?;
+    merge_dir(staged.path(), dest).map_err(|err| DownloadError::Archive(ArchiveError::Move(err)))
 }
 
 fn download_tar_gz(
@@ -386,14 +1537,215 @@ fn download_tar_gz(
     url: &str,
     strip_prefix: Option<&Path>,
     dest: &Path,
+    policy: DownloadPolicy,
 ) -> Result<(), DownloadError> {
-    let (response, mut bar) = download_progress(client, name, url)?;
-    let response = TeeReader::new(response, &mut bar);
-    let response = GzDecoder::new(response);
-    unarchive(response, strip_prefix, dest).map_err(DownloadError::Archive)?;
+    let staged = download_verified(client, name, url, policy, |response| {
+        unarchive_staged(GzDecoder::new(response), strip_prefix, dest)
+    })?;
+    merge_dir(staged.path(), dest).map_err(|err| DownloadError::Archive(ArchiveError::Move(err)))
+}
+
+/// Unpacks an archive into a new directory next to `dest`, from where it is
+/// only moved into `dest` once the download turns out to be intact.
+fn unarchive_staged<R: Read>(r: R, strip_prefix: Option<&Path>, dest: &Path) -> Result<TempDir, DownloadError> {
+    let staging = TempDir::new_in(dest.parent().unwrap_or(dest), "unverified")
+        .map_err(|err| DownloadError::Archive(ArchiveError::CreateDir(err)))?;
+    unarchive(r, strip_prefix, staging.path()).map_err(DownloadError::Archive)?;
+    Ok(staging)
+}
+
+/// Moves everything in `src` into `dest`, merging the directories that are
+/// in both, as the components of a toolchain share e.g. `lib`.
+fn merge_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+        if is_dir && target.is_dir() {
+            merge_dir(&entry.path(), &target)?;
+        } else if let Err(err) = fs::rename(entry.path(), &target) {
+            // another component may have just moved the same directory in
+            if !(is_dir && target.is_dir()) {
+                return Err(err);
+            }
+            merge_dir(&entry.path(), &target)?;
+        }
+    }
     Ok(())
 }
 
+/// The checksum published next to `url`, if any; nightlies have them, CI
+/// artifacts don't.
+fn expected_sha256(client: &Client, url: &str) -> Option<String> {
+    let checksum_url = format!("{}.sha256", url);
+    if let Some(path) = local_path(&checksum_url) {
+        return fs::read_to_string(path)
+            .ok()
+            .and_then(|text| sha256::parse_checksum_file(&text));
+    }
+    let text = client
+        .get(&checksum_url)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|mut response| response.text());
+    match text {
+        Ok(text) => sha256::parse_checksum_file(&text),
+        Err(err) => {
+            debug!("no checksum for {}: {}", url, err);
+            None
+        }
+    }
+}
+
+/// Downloads `url` and hands it to `consume`, returning what it made of the
+/// download only once that is known to be intact. With `policy.checksums`,
+/// and if the server publishes a checksum for it, the download is checked
+/// against that, even when `consume` fails, since a corrupted tarball usually
+/// fails to extract. A mismatch, or a download that broke off while `consume`
+/// was reading it, is downloaded again from the start, up to
+/// `policy.retries` times.
+fn download_verified<T, F>(
+    client: &Client,
+    name: &str,
+    url: &str,
+    policy: DownloadPolicy,
+    mut consume: F,
+) -> Result<T, DownloadError>
+where
+    F: FnMut(&mut dyn Read) -> Result<T, DownloadError>,
+{
+    let retries = policy.retries;
+    let expected = if policy.checksums {
+        expected_sha256(client, url)
+    } else {
+        None
+    };
+    let mut attempt = 0;
+    loop {
+        let (response, mut bar) = download_progress(client, name, url, retries)?;
+        let mut response = sha256::HashingReader::new(TeeReader::new(response, &mut bar));
+        let consumed = consume(&mut response);
+        let mismatch = match expected {
+            Some(ref expected) => match response.finish() {
+                Ok(ref actual) if actual != expected => Some(actual.clone()),
+                // a download that broke off makes `consumed` fail
+                _ => None,
+            },
+            None => None,
+        };
+        match (consumed, mismatch) {
+            (Ok(consumed), None) => return Ok(consumed),
+            (_, Some(actual)) => {
+                if attempt >= retries {
+                    return Err(DownloadError::Checksum {
+                        url: url.to_string(),
+                        expected: expected.unwrap(),
+                        actual,
+                    });
+                }
+                eprintln!("checksum mismatch for {}, retrying", url);
+            }
+            (Err(err), None) => {
+                if attempt >= retries || !err.is_interrupted_download() {
+                    return Err(err);
+                }
+                let delay = retry_delay(attempt);
+                eprintln!(
+                    "warning: downloading {} failed ({}); retrying in {}s",
+                    url,
+                    err,
+                    delay.as_secs()
+                );
+                thread::sleep(delay);
+            }
+        }
+        attempt += 1;
+    }
+}
+
+#[test]
+fn test_checksum_mismatch() {
+    let dir = TempDir::new("bisect-checksum").unwrap();
+    let archive = dir.path().join("rustc.tar.gz");
+    {
+        let file = fs::File::create(&archive).unwrap();
+        let gz = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut builder = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(2);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder.append_data(&mut header, "rustc/bin/rustc", &b"hi"[..]).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+    let checksum = format!("{}  rustc.tar.gz\n", "0".repeat(64));
+    fs::write(dir.path().join("rustc.tar.gz.sha256"), checksum).unwrap();
+    let url = reqwest::Url::from_file_path(&archive).unwrap().to_string();
+    let client = Client::new();
+    let policy = DownloadPolicy {
+        retries: 2,
+        checksums: true,
+    };
+
+    let mut attempts = 0;
+    let res = download_verified(&client, "rustc", &url, policy, |response| {
+        attempts += 1;
+        io::copy(response, &mut io::sink()).map_err(DownloadError::Local)?;
+        Ok(())
+    });
+    match res {
+        Err(DownloadError::Checksum { .. }) => {}
+        other => panic!("expected a checksum mismatch, got {:?}", other),
+    }
+    assert_eq!(attempts, 3);
+
+    // nothing is unpacked from a download that doesn't match
+    let dest = dir.path().join("toolchain");
+    fs::create_dir(&dest).unwrap();
+    match download_tar_gz(&client, "rustc", &url, Some(Path::new("rustc")), &dest, policy) {
+        Err(DownloadError::Checksum { .. }) => {}
+        other => panic!("expected a checksum mismatch, got {:?}", other),
+    }
+    assert_eq!(fs::read_dir(&dest).unwrap().count(), 0);
+
+    let unchecked = DownloadPolicy {
+        retries: 0,
+        checksums: false,
+    };
+    download_tar_gz(&client, "rustc", &url, Some(Path::new("rustc")), &dest, unchecked).unwrap();
+    assert!(dest.join("bin").join("rustc").is_file());
+    // the staging directories are gone
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+}
+
+#[test]
+fn test_interrupted_download() {
+    let dir = TempDir::new("bisect-interrupted").unwrap();
+    let file = dir.path().join("rustc.tar.gz");
+    fs::write(&file, b"tarball").unwrap();
+    let url = reqwest::Url::from_file_path(&file).unwrap().to_string();
+    let client = Client::new();
+    let policy = DownloadPolicy {
+        retries: 1,
+        checksums: false,
+    };
+
+    let mut attempts = 0;
+    let res = download_verified(&client, "rustc", &url, policy, |response| {
+        attempts += 1;
+        let mut contents = Vec::new();
+        response.read_to_end(&mut contents).map_err(DownloadError::Local)?;
+        if attempts == 1 {
+            let dropped = io::Error::new(io::ErrorKind::ConnectionReset, "connection reset");
+            return Err(DownloadError::Archive(ArchiveError::Archive(dropped)));
+        }
+        Ok(contents)
+    });
+    assert_eq!(res.unwrap(), b"tarball");
+    assert_eq!(attempts, 2);
+}
+
 fn unarchive<R: Read>(r: R, strip_prefix: Option<&Path>, dest: &Path) -> Result<(), ArchiveError> {
     for entry in Archive::new(r).entries().map_err(ArchiveError::Archive)? {
         let mut entry = entry.map_err(ArchiveError::Archive)?;
@@ -421,13 +1773,104 @@ fn download_tarball(
     url: &str,
     strip_prefix: Option<&Path>,
     dest: &Path,
+    policy: DownloadPolicy,
+    cache_dir: Option<&Path>,
 ) -> Result<(), DownloadError> {
-    match download_tar_xz(client, name, &format!("{}.xz", url,), strip_prefix, dest) {
+    if let Some(cache_dir) = cache_dir {
+        return download_cached_tarball(client, name, url, strip_prefix, dest, policy, cache_dir);
+    }
+    match download_tar_xz(client, name, &format!("{}.xz", url,), strip_prefix, dest, policy) {
         Ok(()) => return Ok(()),
         Err(DownloadError::NotFound { .. }) => {}
         Err(e) => return Err(e),
     }
-    download_tar_gz(client, name, &format!("{}.gz", url,), strip_prefix, dest)
+    download_tar_gz(client, name, &format!("{}.gz", url,), strip_prefix, dest, policy)
+}
+
+/// Like `download_tarball`, but reuses the tarball from `cache_dir` if an
+/// earlier run downloaded it, and otherwise keeps it there. Tarballs are
+/// stored under their URL, without the scheme.
+fn download_cached_tarball(
+    client: &Client,
+    name: &str,
+    url: &str,
+    strip_prefix: Option<&Path>,
+    dest: &Path,
+    policy: DownloadPolicy,
+    cache_dir: &Path,
+) -> Result<(), DownloadError> {
+    for ext in &["xz", "gz"] {
+        let url = format!("{}.{}", url, ext);
+        let path = cache_dir.join(url.splitn(2, "://").last().unwrap());
+        if path.is_file() {
+            debug!("using {} from the download cache", path.display());
+        } else {
+            // saved under a temporary name of this run's own, and only put
+            // in the cache once it matches its checksum
+            let mut partial = path.clone().into_os_string();
+            partial.push(format!(".part.{}", process::id()));
+            let partial = PathBuf::from(partial);
+            match download_verified(client, name, &url, policy, |response| save_to_file(response, &partial)) {
+                Ok(()) => fs::rename(&partial, &path).map_err(DownloadError::Cache)?,
+                Err(DownloadError::NotFound(_)) => continue,
+                Err(err) => {
+                    let _ = fs::remove_file(&partial);
+                    return Err(err);
+                }
+            }
+        }
+
+        let file = fs::File::open(&path).map_err(DownloadError::Cache)?;
+        let res = if *ext == "xz" {
+            unarchive(XzDecoder::new(file), strip_prefix, dest)
+        } else {
+            unarchive(GzDecoder::new(file), strip_prefix, dest)
+        };
+        if res.is_err() {
+            // most likely damaged; download it again next time
+            let _ = fs::remove_file(&path);
+        }
+        return res.map_err(DownloadError::Archive);
+    }
+    Err(DownloadError::NotFound(format!("{}.gz", url)))
+}
+
+/// Saves a download to `path`, replacing anything saved there before.
+/// Failing to read the download is told apart from failing to write the
+/// file, since only the former is worth downloading again for.
+fn save_to_file(response: &mut dyn Read, path: &Path) -> Result<(), DownloadError> {
+    let mut file = fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::File::create(path))
+        .map_err(DownloadError::Cache)?;
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = match response.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(DownloadError::Transfer(err)),
+        };
+        file.write_all(&buf[..n]).map_err(DownloadError::Cache)?;
+    }
+}
+
+#[test]
+fn test_save_to_file_read_error() {
+    struct Dropped;
+    impl Read for Dropped {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection reset"))
+        }
+    }
+
+    let dir = TempDir::new("bisect-save").unwrap();
+    let path = dir.path().join("cache").join("rustc.tar.xz.part.1");
+    match save_to_file(&mut Dropped, &path) {
+        Err(err @ DownloadError::Transfer(_)) => assert!(err.is_interrupted_download()),
+        other => panic!("expected a transfer error, got {:?}", other),
+    }
+    save_to_file(&mut &b"tarball"[..], &path).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"tarball");
 }
 
 #[derive(Fail, Debug)]
@@ -440,413 +1883,4051 @@ enum InstallError {
     TempDir(#[cause] io::Error),
     #[fail(display = "Could not move tempdir into destination: {}", _0)]
     Move(#[cause] io::Error),
+    #[fail(
+        display = "Only {} of disk space left for toolchains, --min-free-space asks for {}",
+        available, required
+    )]
+    LowDiskSpace { available: String, required: String },
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum TestOutcome {
     Baseline,
     Regressed,
+    /// The run could not be classified either way.
+    Unknown,
 }
 
-impl Toolchain {
-    /// This returns the date of the default toolchain, if it is a nightly toolchain.
-    /// Returns `None` if the installed toolchain is not a nightly toolchain.
-    fn default_nightly() -> Option<Date<Utc>> {
-        let version_meta = rustc_version::version_meta().unwrap();
+impl FromStr for TestOutcome {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<TestOutcome, Error> {
+        match s {
+            "regressed" => Ok(TestOutcome::Regressed),
+            "baseline" => Ok(TestOutcome::Baseline),
+            "unknown" => Ok(TestOutcome::Unknown),
+            _ => bail!("expected regressed, baseline or unknown, got `{}`", s),
+        }
+    }
+}
 
-        if let Channel::Nightly = version_meta.channel {
-            if let Some(str_date) = version_meta.commit_date {
-                let regex = Regex::new(r"(?m)^(\d{4})-(\d{2})-(\d{2})$").unwrap();
-                if let Some(cap) = regex.captures(&str_date) {
-                    let year = cap.get(1)?.as_str().parse::<i32>().ok()?;
-                    let month = cap.get(2)?.as_str().parse::<u32>().ok()?;
-                    let day = cap.get(3)?.as_str().parse::<u32>().ok()?;
+/// How the log enabled with `RUST_LOG` is written; selected with
+/// `--log-format`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LogFormat {
+    Human,
+    Json,
+}
 
-                    return Some(Date::from_utc(
-                        naive::NaiveDate::from_ymd(year, month, day),
-                        Utc,
-                    ));
-                }
-            }
+impl FromStr for LogFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<LogFormat, Error> {
+        match s {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("expected human or json, got `{}`", s),
         }
+    }
+}
 
-        None
+fn init_logger(format: LogFormat) -> Result<(), Error> {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = json!({
+                "level": record.level().to_string(),
+                "module": record.module_path(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
     }
+    builder.try_init()?;
+    Ok(())
+}
 
-    fn is_current_nightly(&self) -> bool {
-        if let ToolchainSpec::Nightly { date } = self.spec {
-            if let Some(default_date) = Self::default_nightly() {
-                return default_date == date;
-            }
-        }
+/// Whether the bisection looks for where a problem appeared or where it went
+/// away; selected with `--bisect-direction`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BisectDirection {
+    Regression,
+    Fix,
+}
 
-        false
+impl FromStr for BisectDirection {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<BisectDirection, Error> {
+        match s {
+            "regression" => Ok(BisectDirection::Regression),
+            "fix" => Ok(BisectDirection::Fix),
+            _ => bail!("expected regression or fix, got `{}`", s),
+        }
     }
+}
 
-    fn remove(&self, dl_params: &DownloadParams) -> Result<(), Error> {
-        if !self.is_current_nightly() {
-            eprintln!("uninstalling {}", self);
-            let dir = dl_params.install_dir.join(self.rustup_name());
-            fs::remove_dir_all(&dir)?;
+impl BisectDirection {
+    /// The outcome of a test from the point of view of the search, which
+    /// always looks for the first toolchain that is `Regressed`: looking for
+    /// a fix, that is the first one that is no longer regressed.
+    fn orient(self, outcome: TestOutcome) -> TestOutcome {
+        match (self, outcome) {
+            (BisectDirection::Fix, TestOutcome::Regressed) => TestOutcome::Baseline,
+            (BisectDirection::Fix, TestOutcome::Baseline) => TestOutcome::Regressed,
+            (_, outcome) => outcome,
         }
+    }
 
-        Ok(())
+    /// Describes the toolchain that was found.
+    fn found(self) -> &'static str {
+        match self {
+            BisectDirection::Regression => "the first toolchain with the regression",
+            BisectDirection::Fix => "the first toolchain with the fix",
+        }
     }
 
-    fn test(&self, cfg: &Config) -> TestOutcome {
-        let outcome = if cfg.args.prompt {
-            loop {
-                let status = self.run_test(cfg);
+    /// What is missing when nothing was found.
+    fn not_found(self) -> &'static str {
+        match self {
+            BisectDirection::Regression => "The regression was not found",
+            BisectDirection::Fix => "The fix was not found",
+        }
+    }
+}
 
-                eprintln!("\n\n{} finished with exit code {:?}.", self, status.code());
-                eprintln!("please select an action to take:");
+/// What the search makes of a toolchain that couldn't be tested; selected
+/// with `--unknown`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum UnknownHandling {
+    /// Probe the neighbours instead, see `least_satisfying`.
+    Skip,
+    TreatAsBaseline,
+    TreatAsRegressed,
+}
 
-                match Select::new()
-                    .items(&["mark regressed", "mark baseline", "retry"])
-                    .default(0)
-                    .interact()
-                    .unwrap()
-                {
-                    0 => break TestOutcome::Regressed,
-                    1 => break TestOutcome::Baseline,
-                    2 => continue,
-                    _ => unreachable!(),
-                }
+impl FromStr for UnknownHandling {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<UnknownHandling, Error> {
+        match s {
+            "skip" => Ok(UnknownHandling::Skip),
+            "treat-as-baseline" => Ok(UnknownHandling::TreatAsBaseline),
+            "treat-as-regressed" => Ok(UnknownHandling::TreatAsRegressed),
+            _ => bail!(
+                "expected skip, treat-as-baseline or treat-as-regressed, got `{}`",
+                s
+            ),
+        }
+    }
+}
+
+impl UnknownHandling {
+    /// The result the search sees for a toolchain whose (oriented) result
+    /// was `result`.
+    fn resolve(self, direction: BisectDirection, result: Satisfies) -> Satisfies {
+        match (self, result) {
+            (UnknownHandling::TreatAsBaseline, Satisfies::Unknown) => {
+                direction.orient(TestOutcome::Baseline).satisfies()
+            }
+            (UnknownHandling::TreatAsRegressed, Satisfies::Unknown) => {
+                direction.orient(TestOutcome::Regressed).satisfies()
             }
+            (_, result) => result,
+        }
+    }
+}
+
+#[test]
+fn test_unknown_handling() {
+    let resolve = |s: &str, direction, result| {
+        s.parse::<UnknownHandling>()
+            .unwrap()
+            .resolve(direction, result)
+    };
+    let (regression, fix) = (BisectDirection::Regression, BisectDirection::Fix);
+    assert_eq!(resolve("skip", regression, Satisfies::Unknown), Satisfies::Unknown);
+    assert_eq!(resolve("treat-as-baseline", regression, Satisfies::Unknown), Satisfies::No);
+    assert_eq!(resolve("treat-as-regressed", regression, Satisfies::Unknown), Satisfies::Yes);
+    assert_eq!(resolve("treat-as-regressed", fix, Satisfies::Unknown), Satisfies::No);
+    assert_eq!(resolve("treat-as-baseline", regression, Satisfies::Yes), Satisfies::Yes);
+    assert!("ignore".parse::<UnknownHandling>().is_err());
+}
+
+impl TestOutcome {
+    fn satisfies(&self) -> Satisfies {
+        // we want to fail, so a successful build doesn't satisfy us
+        match *self {
+            TestOutcome::Baseline => Satisfies::No,
+            TestOutcome::Regressed => Satisfies::Yes,
+            TestOutcome::Unknown => Satisfies::Unknown,
+        }
+    }
+}
+
+/// Decides how the output of a test run maps to a `TestOutcome`; selected with
+/// `--regress`.
+#[derive(Clone, Debug, PartialEq)]
+enum OutputProcessingMode {
+    /// Regressed when the test command exits with an error.
+    RegressOnErrorStatus,
+    /// Regressed when the test command exits successfully.
+    RegressOnSuccessStatus,
+    /// Regressed when the compiler hits an internal compiler error.
+    RegressOnIceAlone,
+    /// Regressed when the compiler does not hit an internal compiler error.
+    RegressOnNotIce,
+    /// Regressed when rustc itself panics, whether or not that is reported as
+    /// an internal compiler error.
+    RegressOnRustcPanic,
+    /// Regressed on anything but a clean compile error: success or an ICE.
+    RegressOnNonCleanError,
+    /// Regressed when a JSON diagnostic matching the filter is emitted.
+    RegressOnDiagnostic(DiagnosticFilter),
+    /// Baseline only when the test command exits successfully and its stdout
+    /// contains the text; regressed otherwise.
+    RegressOnStdoutExit(String),
+    /// Regressed when stderr reports a panic whose message contains the text.
+    RegressOnPanicMessage(String),
+    /// Regressed when the test command exits with exactly this code.
+    RegressOnExitCode(i32),
+    /// Regressed when stdout or stderr contains the text, whatever the exit
+    /// status.
+    RegressOnOutputContains(String),
+    /// Regressed when the compiler hits an internal compiler error and stderr
+    /// matches the pattern.
+    RegressOnIceMessage(MessagePattern),
+    /// Regressed when the test command takes longer than the limit.
+    RegressOnSlowerThan(TimeLimit),
+    /// Regressed when the compiler, or the test command itself, crashed
+    /// instead of exiting, e.g. with a segfault.
+    RegressOnCrash,
+    /// Regressed when stderr has a warning, matching the pattern if there is
+    /// one, that the start of the range didn't have.
+    RegressOnNewWarning(Option<MessagePattern>),
+    /// Regressed when every one of the modes says so (`a&b`).
+    RegressOnAll(Vec<OutputProcessingMode>),
+    /// Regressed when any one of the modes says so (`a|b`).
+    RegressOnAny(Vec<OutputProcessingMode>),
+}
+
+/// How long a test may take with `--regress=slower-than`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TimeLimit {
+    Millis(u64),
+    /// A multiple of the time the start of the range takes.
+    TimesBaseline(f64),
+}
+
+impl TimeLimit {
+    fn of(self, baseline: Option<std::time::Duration>) -> Option<std::time::Duration> {
+        match self {
+            TimeLimit::Millis(ms) => Some(std::time::Duration::from_millis(ms)),
+            TimeLimit::TimesBaseline(factor) => baseline.map(|baseline| {
+                std::time::Duration::from_millis((millis(baseline) as f64 * factor) as u64)
+            }),
+        }
+    }
+}
+
+/// What the test did with the toolchain at the start of the range, which
+/// some `--regress` modes compare the others with.
+#[derive(Debug, Default)]
+struct Baseline {
+    /// How long the test took, for `slower-than:<FACTOR>x`.
+    time: Option<std::time::Duration>,
+    /// The warnings it printed, for `new-warning`.
+    warnings: Option<HashSet<String>>,
+}
+
+/// The distinct warnings in `stderr`, by their first line, leaving out the
+/// summaries like `warning: 2 warnings emitted`, whose wording and counts
+/// change between toolchains.
+fn warning_lines(stderr: &str) -> HashSet<String> {
+    stderr
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| line.starts_with("warning:") || line.starts_with("warning["))
+        .filter(|line| {
+            !(line.ends_with("emitted")
+                || line.contains(") generated ")
+                || line.starts_with("warning: build failed"))
+        })
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_new_warning() {
+    use std::os::unix::process::ExitStatusExt;
+    let success = process::ExitStatus::from_raw(0);
+    let baseline = Baseline {
+        time: None,
+        warnings: Some(warning_lines("warning: unused variable: `x`\n")),
+    };
+    let regressed = |mode: &str, stderr: &str, baseline: &Baseline| {
+        mode.parse::<OutputProcessingMode>()
+            .unwrap()
+            .regressed(success, "", stderr, None, baseline)
+    };
+    let stderr = "warning: unused variable: `x`\nwarning: unnecessary parentheses around `if` condition\n";
+    assert_eq!(regressed("new-warning", "warning: unused variable: `x`\n", &baseline), Some(false));
+    assert_eq!(regressed("new-warning", stderr, &baseline), Some(true));
+    assert_eq!(regressed("new-warning:parentheses", stderr, &baseline), Some(true));
+    assert_eq!(regressed("new-warning:unused_imports", stderr, &baseline), Some(false));
+    assert_eq!(regressed("new-warning", stderr, &Baseline::default()), None);
+}
+
+#[test]
+fn test_warning_lines() {
+    let stderr = "warning: unused variable: `x`\n \
+                  --> src/main.rs:2:9\n\
+                  warning[E0170]: pattern binding `A` is named the same as one of the variants\n\
+                  warning: unused variable: `x`\n\
+                  warning: `foo` (bin \"foo\") generated 3 warnings\n\
+                  warning: 3 warnings emitted\n";
+    let mut warnings = warning_lines(stderr).into_iter().collect::<Vec<_>>();
+    warnings.sort();
+    assert_eq!(
+        warnings,
+        vec![
+            "warning: unused variable: `x`",
+            "warning[E0170]: pattern binding `A` is named the same as one of the variants",
+        ]
+    );
+}
+
+/// A regular expression compiled once, when the `--regress` mode is parsed.
+#[derive(Clone, Debug)]
+struct MessagePattern(Regex);
+
+impl PartialEq for MessagePattern {
+    fn eq(&self, other: &MessagePattern) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+struct RegressMode {
+    syntax: &'static str,
+    description: &'static str,
+    example: &'static str,
+}
+
+/// Every supported `--regress` mode. `--list-regress-modes` prints this table,
+/// and each example must parse as an `OutputProcessingMode`.
+const REGRESS_MODES: &[RegressMode] = &[
+    RegressMode {
+        syntax: "error",
+        description: "regressed if the test command exits with an error (the default)",
+        example: "error",
+    },
+    RegressMode {
+        syntax: "non-error",
+        description: "regressed if the test command succeeds or hits an ICE; \
+                      a clean compile error is the baseline",
+        example: "non-error",
+    },
+    RegressMode {
+        syntax: "ice",
+        description: "regressed if the compiler hits an internal compiler error",
+        example: "ice",
+    },
+    RegressMode {
+        syntax: "non-ice",
+        description: "regressed if the compiler does not hit an internal compiler error",
+        example: "non-ice",
+    },
+    RegressMode {
+        syntax: "rustc-panic",
+        description: "regressed if rustc panics (`thread 'rustc' panicked at`), even when no \
+                      internal compiler error is reported",
+        example: "rustc-panic",
+    },
+    RegressMode {
+        syntax: "crash",
+        description: "regressed if the compiler or the test command crashes, e.g. with a \
+                      segfault (killed by a signal on Unix, an exception code on Windows); \
+                      ordinary errors are the baseline",
+        example: "crash",
+    },
+    RegressMode {
+        syntax: "success",
+        description: "regressed if the test command exits successfully",
+        example: "success",
+    },
+    RegressMode {
+        syntax: "diagnostic:<CODE>|level=<LEVEL>|file=<PATH>",
+        description: "regressed if the build emits a matching diagnostic; cargo is run \
+                      with `--message-format=json` to match on the error code, level or \
+                      span file instead of the message text",
+        example: "diagnostic:E0277",
+    },
+    RegressMode {
+        syntax: "stdout-exit:<TEXT>",
+        description: "baseline if the test command exits successfully and prints TEXT to \
+                      stdout, regressed otherwise; meant for a --script that runs the \
+                      compiled program",
+        example: "stdout-exit:OK",
+    },
+    RegressMode {
+        syntax: "panic-message:<TEXT>",
+        description: "regressed if a program panics with a message containing TEXT; meant \
+                      for a --script that runs the compiled program",
+        example: "panic-message:attempt to subtract with overflow",
+    },
+    RegressMode {
+        syntax: "exit-code:<N>",
+        description: "regressed if the test command exits with code N, baseline on any \
+                      other exit; meant for a --script that reports the outcome itself",
+        example: "exit-code:2",
+    },
+    RegressMode {
+        syntax: "contains:<TEXT>",
+        description: "regressed if the output (stdout or stderr) contains TEXT, whatever the \
+                      exit status; e.g. to look for one specific ICE message",
+        example: "contains:no type for local variable",
+    },
+    RegressMode {
+        syntax: "ice-message:<REGEX>",
+        description: "regressed if the compiler hits an internal compiler error and stderr \
+                      matches the regular expression REGEX, e.g. for an ICE message that \
+                      names a different type each time",
+        example: "ice-message:no type for local variable \\d+",
+    },
+    RegressMode {
+        syntax: "slower-than:<MS>|<FACTOR>x",
+        description: "regressed if the test command takes longer than MS milliseconds, or \
+                      FACTOR times as long as the start of the range, which is timed first; \
+                      a failing test counts as unknown",
+        example: "slower-than:1.5x",
+    },
+    RegressMode {
+        syntax: "new-warning, new-warning:<REGEX>",
+        description: "regressed if stderr has a warning that the start of the range, which is \
+                      tested first, didn't have; with REGEX, only warnings whose first line \
+                      matches it count, e.g. to find where one lint appeared",
+        example: "new-warning:unnecessary parentheses",
+    },
+    RegressMode {
+        syntax: "<MODE>&<MODE>, <MODE>|<MODE>",
+        description: "combines modes: regressed if all of the modes joined with `&`, or any \
+                      of the modes joined with `|`, say so; `&` binds tighter than `|`, so \
+                      `a&b|c` is `(a and b) or c`. A literal `&` or `|` in a mode's text or \
+                      regular expression is written twice, e.g. `ice-message:foo||bar`",
+        example: "ice&contains:foo",
+    },
+];
+
+/// Splits `s` at each `op`, where a doubled `op` stands for a literal one.
+fn split_unescaped(s: &str, op: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != op {
+            parts.last_mut().unwrap().push(c);
+        } else if chars.peek() == Some(&op) {
+            chars.next();
+            parts.last_mut().unwrap().push(op);
+        } else {
+            parts.push(String::new());
+        }
+    }
+    parts
+}
+
+impl FromStr for OutputProcessingMode {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<OutputProcessingMode, Error> {
+        let mut any = split_unescaped(s, '|')
+            .iter()
+            .map(|alternative| parse_conjunction(alternative))
+            .collect::<Result<Vec<_>, Error>>()?;
+        if any.len() == 1 {
+            Ok(any.remove(0))
         } else {
-            if self.run_test(cfg).success() {
-                TestOutcome::Baseline
+            Ok(OutputProcessingMode::RegressOnAny(any))
+        }
+    }
+}
+
+/// Parses modes joined with `&`, i.e. one alternative of an `|`.
+fn parse_conjunction(s: &str) -> Result<OutputProcessingMode, Error> {
+    let mut all = split_unescaped(s, '&')
+        .iter()
+        .map(|mode| parse_single_mode(mode))
+        .collect::<Result<Vec<_>, Error>>()?;
+    if all.len() == 1 {
+        Ok(all.remove(0))
+    } else {
+        Ok(OutputProcessingMode::RegressOnAll(all))
+    }
+}
+
+/// Parses one `--regress` mode, without `&` or `|`.
+fn parse_single_mode(s: &str) -> Result<OutputProcessingMode, Error> {
+    let mode = match s {
+        "error" => OutputProcessingMode::RegressOnErrorStatus,
+        "non-error" => OutputProcessingMode::RegressOnNonCleanError,
+        "ice" => OutputProcessingMode::RegressOnIceAlone,
+        "non-ice" => OutputProcessingMode::RegressOnNotIce,
+        "rustc-panic" => OutputProcessingMode::RegressOnRustcPanic,
+        "crash" => OutputProcessingMode::RegressOnCrash,
+        "success" => OutputProcessingMode::RegressOnSuccessStatus,
+        "new-warning" => OutputProcessingMode::RegressOnNewWarning(None),
+        _ if s.starts_with("diagnostic:") => {
+            OutputProcessingMode::RegressOnDiagnostic(s["diagnostic:".len()..].parse()?)
+        }
+        _ if s.starts_with("stdout-exit:") => {
+            let text = &s["stdout-exit:".len()..];
+            if text.is_empty() {
+                bail!("--regress=stdout-exit needs the text to look for, e.g. stdout-exit:OK");
+            }
+            OutputProcessingMode::RegressOnStdoutExit(text.to_string())
+        }
+        _ if s.starts_with("panic-message:") => {
+            let text = &s["panic-message:".len()..];
+            if text.is_empty() {
+                bail!("--regress=panic-message needs the text to look for, e.g. panic-message:oops");
+            }
+            OutputProcessingMode::RegressOnPanicMessage(text.to_string())
+        }
+        _ if s.starts_with("contains:") => {
+            let text = &s["contains:".len()..];
+            if text.is_empty() {
+                bail!("--regress=contains needs the text to look for, e.g. contains:E0642");
+            }
+            OutputProcessingMode::RegressOnOutputContains(text.to_string())
+        }
+        _ if s.starts_with("ice-message:") => {
+            let pattern = &s["ice-message:".len()..];
+            if pattern.is_empty() {
+                bail!("--regress=ice-message needs a regular expression, e.g. ice-message:unexpected type .*");
+            }
+            match Regex::new(pattern) {
+                Ok(regex) => OutputProcessingMode::RegressOnIceMessage(MessagePattern(regex)),
+                Err(err) => bail!("--regress=ice-message has an invalid regular expression: {}", err),
+            }
+        }
+        _ if s.starts_with("new-warning:") => {
+            let pattern = &s["new-warning:".len()..];
+            match Regex::new(pattern) {
+                Ok(regex) => OutputProcessingMode::RegressOnNewWarning(Some(MessagePattern(regex))),
+                Err(err) => bail!("--regress=new-warning has an invalid regular expression: {}", err),
+            }
+        }
+        _ if s.starts_with("slower-than:") => {
+            let limit = &s["slower-than:".len()..];
+            let parsed = if limit.ends_with('x') {
+                limit[..limit.len() - 1]
+                    .parse()
+                    .ok()
+                    .filter(|&factor: &f64| factor > 0.0)
+                    .map(TimeLimit::TimesBaseline)
             } else {
-                TestOutcome::Regressed
+                limit.parse().ok().map(TimeLimit::Millis)
+            };
+            match parsed {
+                Some(limit) => OutputProcessingMode::RegressOnSlowerThan(limit),
+                None => bail!(
+                    "--regress=slower-than needs milliseconds or a factor, e.g. \
+                     slower-than:5000 or slower-than:1.5x, got `{}`",
+                    limit
+                ),
             }
-        };
+        }
+        _ if s.starts_with("exit-code:") => {
+            let code = &s["exit-code:".len()..];
+            match code.parse() {
+                Ok(code) => OutputProcessingMode::RegressOnExitCode(code),
+                Err(_) => bail!("--regress=exit-code needs an exit code, e.g. exit-code:2, got `{}`", code),
+            }
+        }
+        _ => bail!(
+            "unknown --regress mode `{}`; see --list-regress-modes for the supported modes",
+            s
+        ),
+    };
+    Ok(mode)
+}
 
-        outcome
+impl OutputProcessingMode {
+    fn must_process_stderr(&self) -> bool {
+        match *self {
+            OutputProcessingMode::RegressOnErrorStatus
+            | OutputProcessingMode::RegressOnSuccessStatus
+            | OutputProcessingMode::RegressOnExitCode(_)
+            | OutputProcessingMode::RegressOnSlowerThan(_) => false,
+
+            OutputProcessingMode::RegressOnIceAlone
+            | OutputProcessingMode::RegressOnNotIce
+            | OutputProcessingMode::RegressOnRustcPanic
+            | OutputProcessingMode::RegressOnCrash
+            | OutputProcessingMode::RegressOnNonCleanError
+            | OutputProcessingMode::RegressOnDiagnostic(_)
+            | OutputProcessingMode::RegressOnStdoutExit(_)
+            | OutputProcessingMode::RegressOnPanicMessage(_)
+            | OutputProcessingMode::RegressOnOutputContains(_)
+            | OutputProcessingMode::RegressOnIceMessage(_)
+            | OutputProcessingMode::RegressOnNewWarning(_) => true,
+
+            OutputProcessingMode::RegressOnAll(ref modes)
+            | OutputProcessingMode::RegressOnAny(ref modes) => {
+                modes.iter().any(|mode| mode.must_process_stderr())
+            }
+        }
     }
 
-    fn run_test(&self, cfg: &Config) -> process::ExitStatus {
-        if !cfg.args.preserve_target {
-            let _ = fs::remove_dir_all(
-                cfg.args
-                    .test_dir
-                    .join(&format!("target-{}", self.rustup_name())),
-            );
+    /// Whether cargo should be asked for `--message-format=json`.
+    fn wants_json_diagnostics(&self) -> bool {
+        match *self {
+            OutputProcessingMode::RegressOnDiagnostic(_) => true,
+            OutputProcessingMode::RegressOnAll(ref modes)
+            | OutputProcessingMode::RegressOnAny(ref modes) => {
+                modes.iter().any(|mode| mode.wants_json_diagnostics())
+            }
+            _ => false,
         }
-        let mut cmd = match cfg.args.script {
-            Some(ref script) => {
-                let mut cmd = Command::new(script);
-                cmd.env("RUSTUP_TOOLCHAIN", self.rustup_name());
-                cmd
+    }
+
+    /// Whether the output counts as regressed; `None` when it can't tell,
+    /// e.g. how long a failed test took.
+    fn regressed(
+        &self,
+        status: process::ExitStatus,
+        stdout_utf8: &str,
+        stderr_utf8: &str,
+        elapsed: Option<std::time::Duration>,
+        baseline: &Baseline,
+    ) -> Option<bool> {
+        let saw_ice = || stderr_utf8.contains("error: internal compiler error");
+
+        let regressed = match *self {
+            OutputProcessingMode::RegressOnErrorStatus => !status.success(),
+            OutputProcessingMode::RegressOnSuccessStatus => status.success(),
+            OutputProcessingMode::RegressOnIceAlone => saw_ice(),
+            OutputProcessingMode::RegressOnNotIce => !saw_ice(),
+            OutputProcessingMode::RegressOnRustcPanic => saw_rustc_panic(stderr_utf8),
+            OutputProcessingMode::RegressOnCrash => {
+                crashed(status) || saw_crash_report(stderr_utf8)
             }
-            None => {
-                let mut cmd = Command::new("cargo");
-                cmd.arg(&format!("+{}", self.rustup_name()));
-                if cfg.args.cargo_args.is_empty() {
-                    cmd.arg("build");
-                } else {
-                    cmd.args(&cfg.args.cargo_args);
+            OutputProcessingMode::RegressOnNonCleanError => status.success() || saw_ice(),
+            OutputProcessingMode::RegressOnDiagnostic(ref filter) => {
+                diagnostics::output_has_diagnostic(filter, stdout_utf8, stderr_utf8)
+            }
+            OutputProcessingMode::RegressOnStdoutExit(ref text) => {
+                !(status.success() && stdout_utf8.contains(text.as_str()))
+            }
+            OutputProcessingMode::RegressOnPanicMessage(ref text) => {
+                saw_panic_message(stderr_utf8, text)
+            }
+            OutputProcessingMode::RegressOnExitCode(code) => status.code() == Some(code),
+            OutputProcessingMode::RegressOnOutputContains(ref text) => {
+                stdout_utf8.contains(text.as_str()) || stderr_utf8.contains(text.as_str())
+            }
+            OutputProcessingMode::RegressOnIceMessage(ref pattern) => {
+                saw_ice() && pattern.0.is_match(stderr_utf8)
+            }
+            OutputProcessingMode::RegressOnSlowerThan(limit) => {
+                match (elapsed, limit.of(baseline.time)) {
+                    // a failure may well be quicker, and says nothing about speed
+                    (Some(elapsed), Some(limit)) if status.success() => elapsed > limit,
+                    _ => return None,
                 }
-                cmd
             }
-        };
-        cmd.current_dir(&cfg.args.test_dir);
-        cmd.env("CARGO_TARGET_DIR", format!("target-{}", self.rustup_name()));
-        if cfg.args.emit_cargo_output() || cfg.args.prompt {
-            cmd.stdout(Stdio::inherit());
-            cmd.stderr(Stdio::inherit());
-        } else {
-            cmd.stdout(Stdio::null());
-            cmd.stderr(Stdio::null());
-        }
-        let status = match cmd.status() {
-            Ok(status) => status,
-            Err(err) => {
-                panic!("failed to run {:?}: {:?}", cmd, err);
+            OutputProcessingMode::RegressOnNewWarning(ref pattern) => {
+                let known = baseline.warnings.as_ref()?;
+                let new = warning_lines(stderr_utf8)
+                    .into_iter()
+                    .filter(|warning| !known.contains(warning))
+                    .filter(|warning| pattern.as_ref().map_or(true, |p| p.0.is_match(warning)))
+                    .collect::<Vec<_>>();
+                for warning in &new {
+                    eprintln!("new warning: {}", warning);
+                }
+                !new.is_empty()
+            }
+            OutputProcessingMode::RegressOnAll(ref modes) => {
+                // a definite "no" from any of them settles it
+                let mut regressed = Some(true);
+                for mode in modes {
+                    match mode.regressed(status, stdout_utf8, stderr_utf8, elapsed, baseline) {
+                        Some(false) => return Some(false),
+                        Some(true) => {}
+                        None => regressed = None,
+                    }
+                }
+                return regressed;
+            }
+            OutputProcessingMode::RegressOnAny(ref modes) => {
+                let mut regressed = Some(false);
+                for mode in modes {
+                    match mode.regressed(status, stdout_utf8, stderr_utf8, elapsed, baseline) {
+                        Some(true) => return Some(true),
+                        Some(false) => {}
+                        None => regressed = None,
+                    }
+                }
+                return regressed;
             }
         };
+        Some(regressed)
+    }
 
-        status
+    /// Whether this mode, or one it is combined with, compares against how
+    /// the test went with the start of the range.
+    fn needs_baseline(&self) -> bool {
+        match *self {
+            OutputProcessingMode::RegressOnSlowerThan(TimeLimit::TimesBaseline(_))
+            | OutputProcessingMode::RegressOnNewWarning(_) => true,
+            OutputProcessingMode::RegressOnAll(ref modes)
+            | OutputProcessingMode::RegressOnAny(ref modes) => {
+                modes.iter().any(|mode| mode.needs_baseline())
+            }
+            _ => false,
+        }
     }
 
-    fn install(&self, client: &Client, dl_params: &DownloadParams) -> Result<(), InstallError> {
-        if self.is_current_nightly() {
-            // pre existing installation
-            return Ok(());
+    /// The `slower-than` limits this mode checks, also within combinations.
+    fn time_limits(&self) -> Vec<TimeLimit> {
+        match *self {
+            OutputProcessingMode::RegressOnSlowerThan(limit) => vec![limit],
+            OutputProcessingMode::RegressOnAll(ref modes)
+            | OutputProcessingMode::RegressOnAny(ref modes) => {
+                modes.iter().flat_map(|mode| mode.time_limits()).collect()
+            }
+            _ => Vec::new(),
         }
+    }
+}
 
-        debug!("installing {}", self);
-        let tmpdir = TempDir::new_in(&dl_params.tmp_dir, &self.rustup_name())
-            .map_err(InstallError::TempDir)?;
-        let dest = dl_params.install_dir.join(self.rustup_name());
-        if dl_params.force_install {
-            let _ = fs::remove_dir_all(&dest);
+fn list_regress_modes() {
+    for mode in REGRESS_MODES {
+        println!("{}", mode.syntax);
+        println!("    {}", mode.description);
+        println!("    example: --regress={}", mode.example);
+    }
+}
+
+#[test]
+fn test_regress_mode_examples_parse() {
+    for mode in REGRESS_MODES {
+        if let Err(err) = mode.example.parse::<OutputProcessingMode>() {
+            panic!("example for `{}` does not parse: {}", mode.syntax, err);
         }
+    }
+}
 
-        if dest.is_dir() {
-            // already installed
-            return Ok(());
+#[test]
+fn test_ice_message_pattern() {
+    match "ice-message:no type for .* \\d+".parse() {
+        Ok(OutputProcessingMode::RegressOnIceMessage(ref pattern)) => {
+            assert!(pattern.0.is_match("error: internal compiler error: no type for local variable 42"));
+            assert!(!pattern.0.is_match("error: internal compiler error: no type for local variable"));
         }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!("ice-message:unclosed (".parse::<OutputProcessingMode>().is_err());
+    assert!("ice-message:".parse::<OutputProcessingMode>().is_err());
+}
 
-        let rustc_filename = format!("rustc-nightly-{}", self.host);
+#[test]
+fn test_slower_than_limit() {
+    let limit = |s: &str| match s.parse() {
+        Ok(OutputProcessingMode::RegressOnSlowerThan(limit)) => Some(limit),
+        _ => None,
+    };
+    let secs = std::time::Duration::from_secs;
+    assert_eq!(limit("slower-than:1500"), Some(TimeLimit::Millis(1500)));
+    assert_eq!(limit("slower-than:1.5x").unwrap().of(Some(secs(10))), Some(secs(15)));
+    assert_eq!(limit("slower-than:2x").unwrap().of(None), None);
+    assert_eq!(limit("slower-than:0x"), None);
+    assert_eq!(limit("slower-than:fast"), None);
+}
 
-        let location = match self.spec {
-            ToolchainSpec::Ci { ref commit, .. } => commit.to_string(),
-            ToolchainSpec::Nightly { ref date } => date.format("%Y-%m-%d").to_string(),
-        };
+#[test]
+fn test_combined_regress_modes() {
+    let parse = |s: &str| s.parse::<OutputProcessingMode>().unwrap();
+    assert_eq!(
+        parse("ice&contains:foo"),
+        OutputProcessingMode::RegressOnAll(vec![
+            OutputProcessingMode::RegressOnIceAlone,
+            OutputProcessingMode::RegressOnOutputContains("foo".to_string()),
+        ])
+    );
+    // `&` binds tighter than `|`
+    assert_eq!(
+        parse("ice&contains:foo|success"),
+        OutputProcessingMode::RegressOnAny(vec![
+            OutputProcessingMode::RegressOnAll(vec![
+                OutputProcessingMode::RegressOnIceAlone,
+                OutputProcessingMode::RegressOnOutputContains("foo".to_string()),
+            ]),
+            OutputProcessingMode::RegressOnSuccessStatus,
+        ])
+    );
+    assert_eq!(
+        parse("contains:a&&b||c"),
+        OutputProcessingMode::RegressOnOutputContains("a&b|c".to_string())
+    );
+    assert!("ice&".parse::<OutputProcessingMode>().is_err());
+    assert!("ice|bogus".parse::<OutputProcessingMode>().is_err());
+}
 
-        // download rustc.
-        if let Err(e) = download_tarball(
-            &client,
-            &format!("rustc for {}", self.host),
-            &format!(
-                "{}/{}/{}.tar",
-                dl_params.url_prefix, location, rustc_filename
-            ),
-            Some(&PathBuf::from(&rustc_filename).join("rustc")),
-            tmpdir.path(),
-        ) {
-            match e {
-                DownloadError::NotFound(url) => {
-                    return Err(InstallError::NotFound {
-                        url: url,
-                        spec: self.spec.clone(),
-                    })
+#[cfg(unix)]
+#[test]
+fn test_combined_regress_modes_outcome() {
+    use std::os::unix::process::ExitStatusExt;
+    let failed = process::ExitStatus::from_raw(101 << 8);
+    let ice = "error: internal compiler error: foo";
+    let regressed = |mode: &str, stderr: &str, elapsed: Option<u64>| {
+        mode.parse::<OutputProcessingMode>().unwrap().regressed(
+            failed,
+            "",
+            stderr,
+            elapsed.map(std::time::Duration::from_millis),
+            &Baseline::default(),
+        )
+    };
+    assert_eq!(regressed("ice&contains:foo", ice, None), Some(true));
+    assert_eq!(regressed("ice&contains:bar", ice, None), Some(false));
+    assert_eq!(regressed("contains:bar|ice", ice, None), Some(true));
+    assert_eq!(regressed("contains:bar|success", ice, None), Some(false));
+    // a failed test says nothing about speed, unless another mode settles it
+    assert_eq!(regressed("ice&slower-than:10", ice, Some(50)), None);
+    assert_eq!(regressed("success&slower-than:10", ice, Some(50)), Some(false));
+    assert_eq!(regressed("ice|slower-than:10", ice, Some(50)), Some(true));
+}
+
+impl Toolchain {
+    /// This returns the date of the default toolchain, if it is a nightly toolchain.
+    /// Returns `None` if the installed toolchain is not a nightly toolchain.
+    fn default_nightly() -> Option<Date<Utc>> {
+        let version_meta = rustc_version::version_meta().unwrap();
+
+        if let Channel::Nightly = version_meta.channel {
+            if let Some(str_date) = version_meta.commit_date {
+                let regex = Regex::new(r"(?m)^(\d{4})-(\d{2})-(\d{2})$").unwrap();
+                if let Some(cap) = regex.captures(&str_date) {
+                    let year = cap.get(1)?.as_str().parse::<i32>().ok()?;
+                    let month = cap.get(2)?.as_str().parse::<u32>().ok()?;
+                    let day = cap.get(3)?.as_str().parse::<u32>().ok()?;
+
+                    return Some(Date::from_utc(
+                        naive::NaiveDate::from_ymd(year, month, day),
+                        Utc,
+                    ));
                 }
-                _ => return Err(InstallError::Download(e)),
             }
         }
 
-        // download libstd.
-        for target in &self.std_targets {
-            let rust_std_filename = format!("rust-std-nightly-{}", target);
-            download_tarball(
-                &client,
-                &format!("std for {}", target),
-                &format!(
-                    "{}/{}/{}.tar",
-                    dl_params.url_prefix, location, rust_std_filename
-                ),
-                Some(&PathBuf::from(&rust_std_filename)
-                    .join(format!("rust-std-{}", target))
-                    .join("lib")),
-                &tmpdir.path().join("lib"),
-            ).map_err(InstallError::Download)?;
-        }
+        None
+    }
 
-        if dl_params.install_cargo {
-            let filename = format!("cargo-nightly-{}", self.host);
-            download_tarball(
-                &client,
-                &format!("cargo for {}", self.host),
-                &format!("{}/{}/{}.tar", dl_params.url_prefix, location, filename,),
-                Some(&PathBuf::from(&filename).join("cargo")),
-                tmpdir.path(),
-            ).map_err(InstallError::Download)?;
+    fn is_current_nightly(&self) -> bool {
+        if let ToolchainSpec::Nightly { date } = self.spec {
+            if let Some(default_date) = Self::default_nightly() {
+                return default_date == date;
+            }
         }
 
-        if dl_params.install_src {
-            let filename = "rust-src-nightly";
-            download_tarball(
-                &client,
-                "rust-src",
-                &format!("{}/{}/{}.tar", dl_params.url_prefix, location, filename,),
-                Some(&PathBuf::from(&filename).join("rust-src")),
-                tmpdir.path(),
-            ).map_err(InstallError::Download)?;
-        }
+        false
+    }
+
+    /// Installs this toolchain by building its commit in a local rust checkout,
+    /// or from `--build-cache` if it was built before.
+    fn build_locally(&self, cfg: &Config, checkout: &Path, dl_params: &DownloadParams) -> Result<(), Error> {
+        let commit = match self.spec {
+            ToolchainSpec::Local { ref commit } => commit,
+            ToolchainSpec::Ci { .. } | ToolchainSpec::Nightly { .. } | ToolchainSpec::Beta { .. } => {
+                bail!("{} is not a local build", self)
+            }
+        };
+        let cached = cfg.args.build_cache.join(commit);
+
+        if cached.exists() {
+            eprintln!("using the cached build of {}", self);
+        } else {
+            eprintln!("building {} in {}", self, checkout.display());
+            let status = Command::new("git")
+                .args(&["checkout", "--detach", "--quiet", commit])
+                .current_dir(checkout)
+                .status()?;
+            if !status.success() {
+                bail!("failed to check out {} ({})", commit, status);
+            }
+
+            let mut cmd = match cfg.args.build_script {
+                Some(ref script) => Command::new(script),
+                None => {
+                    let mut cmd = Command::new("python");
+                    cmd.args(&["x.py", "build", "--stage", "1", "src/libstd"]);
+                    cmd
+                }
+            };
+            cmd.current_dir(checkout);
+            if !cfg.args.emit_cargo_output() {
+                cmd.stdout(Stdio::null());
+                cmd.stderr(Stdio::null());
+            }
+            let status = cmd
+                .status()
+                .map_err(|err| format_err!("failed to run {:?}: {}", cmd, err))?;
+            if !status.success() {
+                bail!("the build failed ({})", status);
+            }
 
-        fs::rename(tmpdir.into_path(), dest).map_err(InstallError::Move)?;
+            let output = match cfg.args.build_output {
+                Some(ref dir) => checkout.join(dir),
+                None => checkout.join("build").join(&self.host).join("stage1"),
+            };
+            // copied under a temporary name first, so that an interrupted
+            // copy isn't mistaken for a cached build
+            let partial = cfg.args.build_cache.join(format!("{}.partial", commit));
+            let _ = fs::remove_dir_all(&partial);
+            copy_dir_all(&output, &partial)
+                .map_err(|err| format_err!("failed to copy {}: {}", output.display(), err))?;
+            fs::rename(&partial, &cached)?;
+        }
 
+        let dest = dl_params.install_dir.join(self.rustup_name());
+        let _ = fs::remove_dir_all(&dest);
+        copy_dir_all(&cached, &dest)?;
+        self.link_into_rustup(dl_params)?;
         Ok(())
     }
-}
-
-struct Config {
-    args: Opts,
-    rustup_tmp_path: PathBuf,
-    toolchains_path: PathBuf,
-    target: String,
-    is_commit: bool,
-}
 
-impl Config {
-    fn from_args(mut args: Opts) -> Result<Config, Error> {
-        if args.host == "unknown" {
-            if let Some(host) = option_env!("HOST") {
-                args.host = host.to_string();
-            } else {
-                bail!(
-                    "Failed to auto-detect host triple and was not specified. Please provide it via --host"
-                );
+    fn remove(&self, dl_params: &DownloadParams) -> Result<(), Error> {
+        if !self.is_current_nightly() {
+            eprintln!("uninstalling {}", self);
+            if let Some(ref link_dir) = dl_params.link_dir {
+                let _ = fs::remove_file(link_dir.join(self.rustup_name()));
+            }
+            if dl_params.preserve_names {
+                let rustup_dir = dl_params.link_dir.as_ref().unwrap_or(&dl_params.install_dir);
+                let _ = fs::remove_file(rustup_dir.join(self.preserved_name()));
             }
+            let dir = dl_params.install_dir.join(self.rustup_name());
+            fs::remove_dir_all(&dir)?;
         }
 
-        let target = args.target.clone().unwrap_or_else(|| args.host.clone());
-        let mut args = args;
-
-        let mut toolchains_path = match env::var_os("RUSTUP_HOME") {
-            Some(h) => PathBuf::from(h),
+        Ok(())
+    }
+
+    fn test(&self, cfg: &Config) -> TestOutcome {
+        let outcomes = cfg
+            .test_targets()
+            .into_iter()
+            .map(|target| self.test_target(cfg, target))
+            .collect::<Vec<_>>();
+        cfg.args.bisect_direction.orient(combined_outcome(&outcomes))
+    }
+
+    /// Tests the toolchain, rerunning the test up to
+    /// `--max-retries-per-toolchain` times while its outcome is unknown.
+    fn test_retrying(&self, cfg: &Config) -> TestOutcome {
+        let first = self.test(cfg);
+        let mut outcome = first;
+        let mut attempt = 0;
+        while outcome == TestOutcome::Unknown
+            && attempt < cfg.args.max_retries_per_toolchain
+            && !cfg.args.prompt
+        {
+            attempt += 1;
+            eprintln!(
+                "could not tell whether {} regressed, retrying ({} of {})",
+                self, attempt, cfg.args.max_retries_per_toolchain
+            );
+            outcome = self.test(cfg);
+        }
+        if outcome != first {
+            eprintln!(
+                "warning: testing {} gave {:?} and then {:?}; the test may be flaky",
+                self, first, outcome
+            );
+        }
+        outcome
+    }
+
+    /// Runs the test once, for `target` if given.
+    fn test_target(&self, cfg: &Config, target: Option<&str>) -> TestOutcome {
+        if let Some(target) = target {
+            eprintln!("testing {} for {}", self, target);
+        }
+        let (outcome, output) = if cfg.args.prompt {
+            loop {
+                let (output, _) = match self.run_test(cfg, false, target) {
+                    Ok(run) => run,
+                    Err(err) => return self.test_failed(err),
+                };
+
+                eprintln!("\n\n{} finished with exit code {:?}.", self, output.status.code());
+                eprintln!("please select an action to take:");
+
+                match prompt_outcome(cfg) {
+                    Some(outcome) => break (outcome, output),
+                    None => continue,
+                }
+            }
+        } else {
+            let (output, elapsed) = match self.run_test(cfg, false, target) {
+                Ok(run) => run,
+                Err(err) => return self.test_failed(err),
+            };
+            self.report_time(cfg, &output, elapsed);
+            if let Some(ref dir) = cfg.args.output_cache {
+                if let Err(err) = output_cache::store(dir, &self.output_name(target), &cfg.test_command(), &output) {
+                    eprintln!("failed to record the output of {}: {}", self, err);
+                }
+            }
+            (self.outcome_of(cfg, &output, Some(elapsed)), output)
+        };
+
+        if outcome != TestOutcome::Baseline {
+            if let Some(ref dir) = cfg.args.keep_failed_logs {
+                self.save_log(dir, target, &output_log(&output));
+            }
+        }
+        if let Some(ref dir) = cfg.args.log_dir {
+            let log = format!("outcome: {:?}\n{}", outcome, output_log(&output));
+            self.save_log(dir, target, &log);
+        }
+        if let Some(target) = target {
+            eprintln!("tested {} for {}, got {:?}", self, target, outcome);
+        }
+
+        outcome
+    }
+
+    /// The name outputs and logs of a test run for `target` are saved under.
+    fn output_name(&self, target: Option<&str>) -> String {
+        match target {
+            Some(target) => format!("{}-for-{}", self.rustup_name(), target),
+            None => self.rustup_name(),
+        }
+    }
+
+    /// The outcome of a test that didn't run to completion.
+    /// Shows how long a successful test run took next to the limits of the
+    /// `--regress` modes that go by time, if any.
+    fn report_time(
+        &self,
+        cfg: &Config,
+        output: &process::Output,
+        elapsed: std::time::Duration,
+    ) {
+        if cfg.args.classifier.is_some() || !output.status.success() {
+            return;
+        }
+        let baseline = cfg.baseline.borrow();
+        for limit in cfg.output_processing_mode().time_limits() {
+            if let Some(limit) = limit.of(baseline.time) {
+                eprintln!(
+                    "the test took {}ms, the limit is {}ms",
+                    millis(elapsed),
+                    millis(limit)
+                );
+            }
+        }
+    }
+
+    fn test_failed(&self, err: Error) -> TestOutcome {
+        if let Some(&TimedOut(_, outcome)) = err.downcast_ref::<TimedOut>() {
+            eprintln!("testing {} {}; counting it as {:?}", self, err, outcome);
+            outcome
+        } else {
+            eprintln!("could not test {}: {}", self, err);
+            TestOutcome::Unknown
+        }
+    }
+
+    /// Classifies the output of a test run that took `elapsed`, if known.
+    fn outcome_of(&self, cfg: &Config, output: &process::Output, elapsed: Option<std::time::Duration>) -> TestOutcome {
+        match cfg.args.classifier {
+            Some(ref classifier) => self.classify(classifier, output),
+            None => cfg.default_outcome_of_output(output, elapsed),
+        }
+    }
+
+    /// Classifies the output recorded in the `--output-cache` for this
+    /// toolchain, if there is one, so that it needn't be installed and tested.
+    fn cached_test(&self, cfg: &Config) -> Option<TestOutcome> {
+        let dir = cfg.args.output_cache.as_ref()?;
+        if !cfg.output_processing_mode().time_limits().is_empty() {
+            // how long it took isn't recorded
+            return None;
+        }
+        let mut outcomes = Vec::new();
+        for target in cfg.test_targets() {
+            let output = output_cache::load(dir, &self.output_name(target), &cfg.test_command())?;
+            outcomes.push(self.outcome_of(cfg, &output, None));
+        }
+        eprintln!("reusing the recorded output of {}", self);
+        Some(cfg.args.bisect_direction.orient(combined_outcome(&outcomes)))
+    }
+
+    /// Hands the output of a test run to the `--classifier` program.
+    fn classify(&self, classifier: &Path, output: &process::Output) -> TestOutcome {
+        let input = json!({
+            "toolchain": self.to_string(),
+            "status": output.status.code(),
+            "success": output.status.success(),
+            "stdout": String::from_utf8_lossy(&output.stdout),
+            "stderr": String::from_utf8_lossy(&output.stderr),
+        });
+
+        let mut child = match Command::new(classifier)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) => {
+                eprintln!("failed to run {}: {}", classifier.display(), err);
+                return TestOutcome::Unknown;
+            }
+        };
+        // written from another thread while the verdict is read, so that a
+        // classifier writing a lot before reading all of its input can't
+        // leave both sides waiting on a full pipe
+        let writer = child.stdin.take().map(|mut stdin| {
+            thread::spawn(move || {
+                // the classifier may legitimately exit without reading everything
+                if let Err(err) = serde_json::to_writer(&mut stdin, &input) {
+                    debug!("failed to write to the classifier: {}", err);
+                }
+            })
+        });
+        let verdict = child.wait_with_output();
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        let verdict = match verdict {
+            Ok(verdict) => verdict,
+            Err(err) => {
+                eprintln!("failed to run {}: {}", classifier.display(), err);
+                return TestOutcome::Unknown;
+            }
+        };
+
+        classifier_verdict(&String::from_utf8_lossy(&verdict.stdout), verdict.status.code())
+    }
+
+    /// Writes a probe's log to `<dir>/<rustup name>.log`.
+    fn save_log(&self, dir: &Path, target: Option<&str>, log: &str) {
+        let path = dir.join(format!("{}.log", self.output_name(target)));
+        let header = match target {
+            Some(target) => format!("toolchain: {}\ntarget: {}\n", self, target),
+            None => format!("toolchain: {}\n", self),
+        };
+        let result = fs::create_dir_all(dir).and_then(|()| fs::write(&path, header + log));
+        match result {
+            Ok(()) => eprintln!("saved the output of {} to {}", self, path.display()),
+            Err(err) => eprintln!("failed to write {}: {}", path.display(), err),
+        }
+    }
+
+    /// The target directory cargo will build into for this toolchain when run
+    /// in `test_dir`, honoring a `--target-dir` passed through to cargo.
+    fn target_dir(&self, cfg: &Config, test_dir: &Path) -> PathBuf {
+        let dir = explicit_target_dir(&cfg.args.cargo_args)
+            .unwrap_or_else(|| PathBuf::from(format!("target-{}", self.rustup_name())));
+        test_dir.join(dir)
+    }
+
+    /// Copies the test directory into a new temporary directory for
+    /// `--fresh-test-dir`, leaving out the target directories of earlier runs.
+    fn fresh_test_dir(&self, cfg: &Config) -> Result<TempDir, Error> {
+        let dir = TempDir::new(&format!("cargo-bisect-{}", self.rustup_name()))?;
+        for entry in fs::read_dir(&cfg.args.test_dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name_str = name.to_string_lossy();
+            if name_str == "target" || name_str.starts_with("target-") {
+                continue;
+            }
+            let dest = dir.path().join(&name);
+            if entry.file_type()?.is_dir() {
+                copy_dir_all(&entry.path(), &dest)?;
+            } else {
+                fs::copy(entry.path(), &dest)?;
+            }
+        }
+        debug!("testing {} in {}", self, dir.path().display());
+        Ok(dir)
+    }
+
+    /// Runs the test command for this toolchain, after any setup scripts. An
+    /// error means a setup script failed and the test never ran.
+    /// With `verbose`, cargo is asked for verbose output and the output is
+    /// always captured rather than shown. With a `target`, the test builds
+    /// for that target. Returns the output along with how long the test
+    /// command took.
+    fn run_test(
+        &self,
+        cfg: &Config,
+        verbose: bool,
+        target: Option<&str>,
+    ) -> Result<(process::Output, std::time::Duration), Error> {
+        let fresh_dir = if cfg.args.fresh_test_dir || cfg.verifying.get() {
+            Some(self.fresh_test_dir(cfg)?)
+        } else {
+            None
+        };
+        let test_dir = fresh_dir.as_ref().map_or(cfg.args.test_dir.as_path(), |d| d.path());
+
+        if cfg.args.clean_between_probes {
+            clean_target_dir(test_dir, &self.target_dir(cfg, test_dir));
+        } else if !cfg.args.preserve_target {
+            let _ = fs::remove_dir_all(test_dir.join(&format!("target-{}", self.rustup_name())));
+        }
+        let mut cmd = match (&cfg.args.script, cfg.tool, &cfg.args.rustc_only) {
+            (&Some(ref script), _, _) => {
+                let mut cmd = Command::new(script);
+                cmd.env("RUSTUP_TOOLCHAIN", self.rustup_name());
+                cmd
+            }
+            (&None, Some(tool), _) => {
+                let mut cmd = Command::new(tool.command[0]);
+                cmd.args(&tool.command[1..]);
+                cmd.args(&cfg.args.cargo_args);
+                cmd.env("RUSTUP_TOOLCHAIN", self.rustup_name());
+                cmd
+            }
+            (&None, None, &Some(ref file)) => {
+                let mut cmd = Command::new("rustc");
+                cmd.arg(&format!("+{}", self.rustup_name()));
+                cmd.args(&cfg.rustc_args(file));
+                if let Some(target) = target {
+                    cmd.arg("--target").arg(target);
+                }
+                cmd.arg("--out-dir").arg(format!("target-{}", self.rustup_name()));
+                cmd
+            }
+            (&None, None, &None) => {
+                let mut cmd = Command::new("cargo");
+                cmd.arg(&format!("+{}", self.rustup_name()));
+                if verbose {
+                    cmd.arg("-vv");
+                }
+                let mut args = cfg.cargo_args();
+                if let Some(target) = target {
+                    let pos = args.iter().position(|a| a == "--").unwrap_or(args.len());
+                    args.insert(pos, OsString::from(target));
+                    args.insert(pos, OsString::from("--target"));
+                }
+                cmd.args(&args);
+                cmd
+            }
+        };
+        if let Some(target) = target {
+            // for a --script or tool, which may run cargo itself
+            cmd.env("CARGO_BUILD_TARGET", target);
+        }
+        if verbose {
+            cmd.env("CARGO_TERM_VERBOSE", "true");
+            cmd.env("RUST_BACKTRACE", "1");
+        }
+        cmd.current_dir(test_dir);
+        cmd.env("CARGO_TARGET_DIR", format!("target-{}", self.rustup_name()));
+        if let Some(flags) = cfg.rustflags() {
+            cmd.env("RUSTFLAGS", flags);
+        }
+        if cfg.args.offline_eval {
+            self.prepare_offline(cfg, test_dir);
+            // equivalent to passing `--offline`, but also reaches any cargo
+            // invocations made by a `--script`
+            cmd.env("CARGO_NET_OFFLINE", "true");
+        }
+
+        let emit_output = !verbose && (cfg.args.emit_cargo_output() || cfg.args.prompt);
+        self.run_setup_scripts(cfg, test_dir, emit_output)?;
+
+        let must_capture_output = verbose || cfg.must_capture_output();
+        // output that isn't shown is still captured, if only to notice when
+        // there is none at all
+        let captured = must_capture_output || !emit_output;
+        let default_stdio = || {
+            if captured {
+                Stdio::piped()
+            } else {
+                Stdio::inherit()
+            }
+        };
+        cmd.stdout(default_stdio());
+        cmd.stderr(default_stdio());
+        let started = Instant::now();
+        let output = match cfg.test_timeout() {
+            Some((secs, outcome)) => match output_with_timeout(&mut cmd, std::time::Duration::from_secs(secs)) {
+                Ok(Some(output)) => output,
+                Ok(None) => return Err(TimedOut(secs, outcome).into()),
+                Err(err) => bail!("failed to run {:?}: {}", cmd, err),
+            },
+            None => match cmd.output() {
+                Ok(output) => output,
+                Err(err) => bail!("failed to run {:?}: {}", cmd, err),
+            },
+        };
+        let elapsed = started.elapsed();
+
+        // if we captured the output to inspect it, still pass it along
+        if must_capture_output && emit_output {
+            io::stdout().write_all(&output.stdout).unwrap();
+            io::stderr().write_all(&output.stderr).unwrap();
+        }
+
+        if captured && output.stdout.is_empty() && output.stderr.is_empty() {
+            eprintln!(
+                "warning: testing {} produced no output at all; check that --test-dir \
+                 points at the project and that the test command builds something",
+                self
+            );
+        }
+
+        if let Some(ref dir) = cfg.args.collect_timings {
+            if cfg.args.script.is_none() {
+                self.collect_timings(cfg, test_dir, dir);
+            }
+        }
+
+        Ok((output, elapsed))
+    }
+
+    /// Runs the `--setup-script`s in order, stopping at the first failure.
+    fn run_setup_scripts(&self, cfg: &Config, test_dir: &Path, emit_output: bool) -> Result<(), Error> {
+        for script in &cfg.args.setup_scripts {
+            let mut cmd = Command::new(script);
+            cmd.current_dir(test_dir);
+            cmd.env("RUSTUP_TOOLCHAIN", self.rustup_name());
+            cmd.env("CARGO_TARGET_DIR", format!("target-{}", self.rustup_name()));
+            if let Some(flags) = cfg.rustflags() {
+                cmd.env("RUSTFLAGS", flags);
+            }
+            if cfg.args.offline_eval {
+                cmd.env("CARGO_NET_OFFLINE", "true");
+            }
+            if !emit_output {
+                cmd.stdout(Stdio::null());
+                cmd.stderr(Stdio::null());
+            }
+            let status = cmd
+                .status()
+                .map_err(|err| format_err!("failed to run {}: {}", script.display(), err))?;
+            if !status.success() {
+                bail!("setup script {} failed ({})", script.display(), status);
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the reports written by `-Z timings` into `dir/<toolchain>/`.
+    /// Depending on the cargo version they end up either in the directory
+    /// cargo ran in or in `<target-dir>/cargo-timings`.
+    fn collect_timings(&self, cfg: &Config, test_dir: &Path, dir: &Path) {
+        let dest = dir.join(self.rustup_name());
+        let sources = [
+            test_dir.to_path_buf(),
+            self.target_dir(cfg, test_dir).join("cargo-timings"),
+        ];
+        let mut collected = 0;
+        for source in &sources {
+            let entries = match fs::read_dir(source) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name();
+                if !name.to_string_lossy().starts_with("cargo-timing") {
+                    continue;
+                }
+                let res = fs::create_dir_all(&dest)
+                    .and_then(|_| fs::copy(entry.path(), dest.join(&name)))
+                    .and_then(|_| fs::remove_file(entry.path()));
+                match res {
+                    Ok(()) => collected += 1,
+                    Err(err) => eprintln!(
+                        "warning: failed to collect {}: {}",
+                        entry.path().display(),
+                        err
+                    ),
+                }
+            }
+        }
+        if collected == 0 {
+            eprintln!("warning: no timings report found for {}", self);
+        } else {
+            eprintln!("collected timings of {} in {}", self, dest.display());
+        }
+    }
+
+    /// Runs `cargo fetch` so that the test itself can then run without network
+    /// access. Failures are reported but don't stop the evaluation.
+    fn prepare_offline(&self, cfg: &Config, test_dir: &Path) {
+        let mut cmd = Command::new("cargo");
+        cmd.arg(&format!("+{}", self.rustup_name()));
+        cmd.arg("fetch");
+        cmd.current_dir(test_dir);
+        if !cfg.args.emit_cargo_output() {
+            cmd.stdout(Stdio::null());
+            cmd.stderr(Stdio::null());
+        }
+        match cmd.status() {
+            Ok(ref status) if status.success() => {}
+            Ok(status) => eprintln!(
+                "warning: `cargo fetch` for {} failed ({}); evaluating offline anyway",
+                self, status
+            ),
+            Err(err) => eprintln!("warning: failed to run {:?}: {}", cmd, err),
+        }
+    }
+
+    /// The tarballs making up this toolchain, to be unpacked into `dest`. The
+    /// first one is always rustc.
+    fn components(&self, dl_params: &DownloadParams, dest: &Path) -> Vec<Component> {
+        let channel = self.spec.channel();
+        let rustc_filename = format!("rustc-{}-{}", channel, self.host);
+
+        let location = match self.spec {
+            ToolchainSpec::Ci { ref commit, .. } | ToolchainSpec::Local { ref commit } => commit.to_string(),
+            ToolchainSpec::Nightly { ref date } | ToolchainSpec::Beta { ref date } => {
+                date.format("%Y-%m-%d").to_string()
+            }
+        };
+
+        let url = |filename: &str| {
+            format!("{}/{}/{}.tar", dl_params.url_prefix, location, filename)
+        };
+
+        // rustc comes first: if it is missing, so is the whole toolchain.
+        let mut components = vec![Component {
+            name: format!("rustc for {}", self.host),
+            url: url(&rustc_filename),
+            strip_prefix: PathBuf::from(&rustc_filename).join("rustc"),
+            dest: dest.to_path_buf(),
+        }];
+
+        // libstd.
+        for target in &self.std_targets {
+            let rust_std_filename = format!("rust-std-{}-{}", channel, target);
+            components.push(Component {
+                name: format!("std for {}", target),
+                url: url(&rust_std_filename),
+                strip_prefix: PathBuf::from(&rust_std_filename)
+                    .join(format!("rust-std-{}", target))
+                    .join("lib"),
+                dest: dest.join("lib"),
+            });
+        }
+
+        if dl_params.install_cargo {
+            let filename = format!("cargo-{}-{}", channel, self.host);
+            components.push(Component {
+                name: format!("cargo for {}", self.host),
+                url: url(&filename),
+                strip_prefix: PathBuf::from(&filename).join("cargo"),
+                dest: dest.to_path_buf(),
+            });
+        }
+
+        if let Some(tool) = dl_params.tool {
+            let filename = format!("{}-{}-{}", tool.name, channel, self.host);
+            components.push(Component {
+                name: format!("{} for {}", tool.name, self.host),
+                url: url(&filename),
+                strip_prefix: PathBuf::from(&filename).join(tool.subdir),
+                dest: dest.to_path_buf(),
+            });
+        }
+
+        if dl_params.install_src {
+            let filename = format!("rust-src-{}", channel);
+            components.push(Component {
+                name: "rust-src".to_string(),
+                url: url(&filename),
+                strip_prefix: PathBuf::from(&filename).join("rust-src"),
+                dest: dest.to_path_buf(),
+            });
+        }
+
+        for name in &dl_params.components {
+            components.push(self.extra_component(name, &url, dest));
+        }
+
+        components
+    }
+
+    /// A component given with `--component`, by its rustup name. The tarball
+    /// holds it in a directory named after the component, `<name>-<host>` for
+    /// host-specific components like rust-analysis or just `<name>` for
+    /// `-preview` tools, while the tarball itself drops the `-preview`.
+    fn extra_component<F: Fn(&str) -> String>(&self, name: &str, url: F, dest: &Path) -> Component {
+        let base = name.trim_end_matches("-preview");
+        let channel = self.spec.channel();
+        let (filename, subdir) = if name == "rust-src" {
+            (format!("rust-src-{}", channel), name.to_string())
+        } else if name.ends_with("-preview") {
+            (format!("{}-{}-{}", base, channel, self.host), name.to_string())
+        } else if let Some(tool) = TOOLS.iter().find(|tool| tool.name == name) {
+            (format!("{}-{}-{}", name, channel, self.host), tool.subdir.to_string())
+        } else {
+            (format!("{}-{}-{}", name, channel, self.host), format!("{}-{}", name, self.host))
+        };
+        Component {
+            name: format!("{} for {}", name, self.host),
+            url: url(&filename),
+            strip_prefix: PathBuf::from(&filename).join(subdir),
+            dest: dest.to_path_buf(),
+        }
+    }
+
+    /// Checks that the toolchains' filesystem has `required` bytes free,
+    /// removing toolchains of earlier bisections, oldest first, if allowed.
+    fn ensure_free_space(&self, dl_params: &DownloadParams, required: u64) -> Result<(), InstallError> {
+        let mut available = match free_space(&dl_params.install_dir) {
+            Some(available) => available,
             None => {
-                let mut home = dirs::home_dir().ok_or_else(|| format_err!("Could not find home."))?;
-                home.push(".rustup");
-                home
+                static WARNED: std::sync::Once = std::sync::Once::new();
+                WARNED.call_once(|| {
+                    eprintln!(
+                        "warning: could not determine the free disk space of {}, so \
+                         --min-free-space has no effect",
+                        dl_params.install_dir.display()
+                    )
+                });
+                return Ok(());
+            }
+        };
+        if available >= required {
+            return Ok(());
+        }
+        eprintln!(
+            "only {} of disk space left for toolchains, --min-free-space asks for {}",
+            format_bytes(available),
+            format_bytes(required)
+        );
+
+        if dl_params.reclaim_space {
+            let name = self.rustup_name();
+            let dated_prefix = format!("{}bisector-", self.name_prefix);
+            let ci_prefix = format!("{}ci-", self.name_prefix);
+            let mut leftovers = fs::read_dir(&dl_params.install_dir)
+                .map(|entries| {
+                    entries
+                        .filter_map(|e| e.ok())
+                        .filter(|e| {
+                            let n = e.file_name().to_string_lossy().into_owned();
+                            n != name
+                                && !dl_params.in_use.contains(&n)
+                                && (n.starts_with(&dated_prefix) || n.starts_with(&ci_prefix))
+                        })
+                        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            leftovers.sort();
+            for (_, path) in leftovers {
+                if available >= required {
+                    break;
+                }
+                eprintln!("removing {} to free space", path.display());
+                let _ = fs::remove_dir_all(&path);
+                if let (Some(link_dir), Some(name)) = (&dl_params.link_dir, path.file_name()) {
+                    let _ = fs::remove_file(link_dir.join(name));
+                }
+                available = free_space(&dl_params.install_dir).unwrap_or(available);
+            }
+        }
+
+        if available >= required {
+            eprintln!("{} of disk space is free now", format_bytes(available));
+            Ok(())
+        } else {
+            Err(InstallError::LowDiskSpace {
+                available: format_bytes(available),
+                required: format_bytes(required),
+            })
+        }
+    }
+
+    fn is_tool_url(&self, dl_params: &DownloadParams, url: &str) -> bool {
+        match dl_params.tool {
+            Some(tool) => url.contains(&format!("/{}-{}-{}", tool.name, self.spec.channel(), self.host)),
+            None => false,
+        }
+    }
+
+    /// The `--component` that `url` downloads, if any.
+    fn component_of_url<'a>(&self, dl_params: &'a DownloadParams, url: &str) -> Option<&'a str> {
+        dl_params
+            .components
+            .iter()
+            .find(|name| {
+                let base = name.trim_end_matches("-preview");
+                let channel = self.spec.channel();
+                url.contains(&format!("/{}-{}-{}", base, channel, self.host))
+                    || (*name == "rust-src" && url.contains(&format!("/rust-src-{}", channel)))
+            })
+            .map(|name| name.as_str())
+    }
+
+    fn install(&self, client: &Client, dl_params: &DownloadParams) -> Result<(), InstallError> {
+        if self.is_current_nightly() {
+            // pre existing installation
+            return Ok(());
+        }
+
+        if let ToolchainSpec::Local { .. } = self.spec {
+            // only `build_locally` can install these; never fetch the CI build
+            return Err(InstallError::NotFound {
+                url: String::new(),
+                spec: self.spec.clone(),
+            });
+        }
+
+        debug!("installing {}", self);
+        if let Some(required) = dl_params.min_free_space {
+            self.ensure_free_space(dl_params, required)?;
+        }
+        let dest = dl_params.install_dir.join(self.rustup_name());
+        if dl_params.force_install {
+            let _ = fs::remove_dir_all(&dest);
+        }
+
+        if dest.is_dir() {
+            // already installed
+            return self.link_into_rustup(dl_params).map_err(InstallError::Move);
+        }
+
+        self.extract_to(client, dl_params, &dest)?;
+        self.link_into_rustup(dl_params).map_err(InstallError::Move)
+    }
+
+    /// Downloads and unpacks the toolchain into `dest`, without making it
+    /// known to rustup.
+    fn extract_to(&self, client: &Client, dl_params: &DownloadParams, dest: &Path) -> Result<(), InstallError> {
+        let tmpdir = TempDir::new_in(&dl_params.tmp_dir, &self.rustup_name())
+            .map_err(InstallError::TempDir)?;
+        let components = self.components(dl_params, tmpdir.path());
+        if let Err(err) = download_components(client, components, dl_params) {
+            if let (_, DownloadError::NotFound(ref url)) = err {
+                if let Some(name) = self.component_of_url(dl_params, url) {
+                    eprintln!("component {} is not published for {}", name, self);
+                }
+            }
+            return Err(match err {
+                // a missing tool is as good as a missing toolchain: it
+                // often fails to build
+                (i, DownloadError::NotFound(url))
+                    if i == 0
+                        || self.is_tool_url(dl_params, &url)
+                        || self.component_of_url(dl_params, &url).is_some() =>
+                {
+                    InstallError::NotFound {
+                        url: url,
+                        spec: self.spec.clone(),
+                    }
+                }
+                (_, e) => InstallError::Download(e),
+            });
+        }
+
+        // The rename is only atomic (and only works at all) when the temporary
+        // directory is on the same filesystem as the toolchains, which may not
+        // be the case with `--install-tmp-dir`.
+        if let Err(err) = fs::rename(tmpdir.path(), dest) {
+            eprintln!(
+                "warning: could not move {} into place ({}); copying it instead",
+                self, err
+            );
+            if let Err(err) = copy_dir_all(tmpdir.path(), dest) {
+                let _ = fs::remove_dir_all(dest);
+                return Err(InstallError::Move(err));
+            }
+        }
+        Ok(())
+    }
+
+    /// With `--artifacts-dir`, makes the installed toolchain visible to rustup
+    /// by linking it into the rustup toolchains directory, and with
+    /// `--preserve-toolchains` links it there under its `preserved_name` too.
+    fn link_into_rustup(&self, dl_params: &DownloadParams) -> io::Result<()> {
+        let installed = dl_params.install_dir.join(self.rustup_name());
+        if let Some(ref link_dir) = dl_params.link_dir {
+            let link = link_dir.join(self.rustup_name());
+            let _ = fs::remove_file(&link);
+            symlink_dir(&installed, &link)?;
+        }
+        if dl_params.preserve_names {
+            let rustup_dir = dl_params.link_dir.as_ref().unwrap_or(&dl_params.install_dir);
+            let link = rustup_dir.join(self.preserved_name());
+            let _ = fs::remove_file(&link);
+            // the toolchain is usable under its own name either way
+            match symlink_dir(&installed, &link) {
+                Ok(()) => eprintln!("{} is available as `{}`", self, self.preserved_name()),
+                Err(err) => eprintln!(
+                    "warning: could not link {} as `{}`: {}",
+                    self,
+                    self.preserved_name(),
+                    err
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A tarball to download and unpack into a toolchain being installed.
+struct Component {
+    name: String,
+    url: String,
+    strip_prefix: PathBuf,
+    dest: PathBuf,
+}
+
+/// Downloads and unpacks the components, `jobs` of them at a time. On failure,
+/// returns the index of the first component that failed along with its error.
+fn download_components(
+    client: &Client,
+    components: Vec<Component>,
+    dl_params: &DownloadParams,
+) -> Result<(), (usize, DownloadError)> {
+    let policy = dl_params.policy();
+    let mut components = components.into_iter().enumerate().peekable();
+    while components.peek().is_some() {
+        let handles = components
+            .by_ref()
+            .take(cmp::max(dl_params.jobs, 1))
+            .map(|(i, c)| {
+                let client = client.clone();
+                let cache_dir = dl_params.cache_dir.clone();
+                let handle = thread::spawn(move || {
+                    download_tarball(
+                        &client,
+                        &c.name,
+                        &c.url,
+                        Some(&c.strip_prefix),
+                        &c.dest,
+                        policy,
+                        cache_dir.as_ref().map(|dir| dir.as_path()),
+                    )
+                });
+                (i, handle)
+            })
+            .collect::<Vec<_>>();
+
+        // wait for the whole batch before bailing, so that nothing is still
+        // writing into the temporary directory once it is removed
+        let mut first_err = None;
+        for (i, handle) in handles {
+            if let Err(e) = handle.join().expect("download thread panicked") {
+                first_err = first_err.or(Some((i, e)));
             }
+        }
+        if let Some(err) = first_err {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(src, dest)
+}
+
+#[cfg(windows)]
+fn symlink_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_dir(src, dest)
+}
+
+fn copy_dir_all(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses a size such as `500M`, `5G` or a plain number of bytes.
+fn parse_size(s: &str) -> Result<u64, Error> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => (&s[..idx], &s[idx..]),
+        None => (s, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => bail!("unknown size unit `{}` in `{}`", unit, s),
+    };
+    match number.parse::<u64>() {
+        Ok(n) => Ok(n * multiplier),
+        Err(_) => bail!("expected a size like 500M or 5G, got `{}`", s),
+    }
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("1024").unwrap(), 1024);
+    assert_eq!(parse_size("500M").unwrap(), 500 << 20);
+    assert_eq!(parse_size("5g").unwrap(), 5 << 30);
+    assert!(parse_size("5X").is_err());
+    assert!(parse_size("G").is_err());
+}
+
+/// The space available to unprivileged users on the filesystem of `path`,
+/// if it can be determined.
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let kilobytes: u64 = stdout.lines().nth(1)?.split_whitespace().nth(3)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Runs `cmd` like `Command::output`, but once `timeout` has passed kills it
+/// along with any processes it started, e.g. the rustc processes of cargo,
+/// and returns `None`.
+fn output_with_timeout(
+    cmd: &mut Command,
+    timeout: std::time::Duration,
+) -> io::Result<Option<process::Output>> {
+    use std::sync::mpsc;
+
+    in_own_process_group(cmd);
+    let mut child = cmd.spawn()?;
+    let tree = ProcessTree::of(&child);
+    let _forwarding = SignalForwarding::to(&child);
+
+    // drain the pipes while waiting, so that the child can't block on them
+    type Reader = (thread::JoinHandle<Vec<u8>>, mpsc::Receiver<()>);
+    fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> Option<Reader> {
+        pipe.map(|mut pipe| {
+            let (done, finished) = mpsc::channel();
+            let handle = thread::spawn(move || {
+                let mut buf = Vec::new();
+                let _ = pipe.read_to_end(&mut buf);
+                let _ = done.send(());
+                buf
+            });
+            (handle, finished)
+        })
+    }
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            tree.kill(&mut child);
+            let _ = child.wait();
+            // with the tree gone the readers see the end of the pipes, unless
+            // something that left the process group still holds them; those
+            // readers are left to finish on their own
+            for (handle, finished) in stdout.into_iter().chain(stderr) {
+                if finished.recv_timeout(std::time::Duration::from_secs(5)).is_ok() {
+                    let _ = handle.join();
+                }
+            }
+            return Ok(None);
+        }
+        thread::sleep(std::time::Duration::from_millis(100));
+    };
+
+    let collect = |reader: Option<Reader>| {
+        reader.and_then(|(r, _)| r.join().ok()).unwrap_or_default()
+    };
+    Ok(Some(process::Output {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+    }))
+}
+
+#[cfg(unix)]
+#[test]
+fn test_output_with_timeout() {
+    let second = std::time::Duration::from_secs(1);
+    let mut echo = Command::new("echo");
+    echo.arg("hi").stdout(Stdio::piped());
+    let output = output_with_timeout(&mut echo, second).unwrap().unwrap();
+    assert_eq!(output.stdout, b"hi\n");
+
+    let started = Instant::now();
+    let mut sleep = Command::new("sh");
+    sleep.args(&["-c", "sleep 30; echo done"]).stdout(Stdio::piped());
+    assert!(output_with_timeout(&mut sleep, second).unwrap().is_none());
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+}
+
+#[cfg(unix)]
+fn in_own_process_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn in_own_process_group(_cmd: &mut Command) {}
+
+/// The process group of the test running in its own, if any, for the
+/// signal handler to pass SIGINT and SIGTERM on to.
+#[cfg(unix)]
+static TEST_PROCESS_GROUP: AtomicUsize = AtomicUsize::new(0);
+
+/// While alive, passes the SIGINT of a Ctrl-C and SIGTERM on to the process
+/// group of a test run by `output_with_timeout`, which the terminal or a
+/// `kill` of this process would otherwise not reach, before they end this
+/// process as usual.
+struct SignalForwarding;
+
+#[cfg(unix)]
+impl SignalForwarding {
+    fn to(child: &process::Child) -> SignalForwarding {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+        INSTALL.call_once(|| unsafe {
+            for &signal in &[libc::SIGINT, libc::SIGTERM] {
+                let handler = forward_signal as extern "C" fn(libc::c_int);
+                let previous = libc::signal(signal, handler as libc::sighandler_t);
+                if previous == libc::SIG_IGN {
+                    // e.g. started in the background; keep ignoring it
+                    libc::signal(signal, libc::SIG_IGN);
+                }
+            }
+        });
+        TEST_PROCESS_GROUP.store(child.id() as usize, Ordering::SeqCst);
+        SignalForwarding
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SignalForwarding {
+    fn drop(&mut self) {
+        TEST_PROCESS_GROUP.store(0, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let group = TEST_PROCESS_GROUP.load(Ordering::SeqCst);
+    unsafe {
+        if group != 0 {
+            libc::kill(-(group as libc::pid_t), signal);
+        }
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+#[cfg(not(unix))]
+impl SignalForwarding {
+    fn to(_child: &process::Child) -> SignalForwarding {
+        // a Ctrl-C already reaches every process attached to the console
+        SignalForwarding
+    }
+}
+
+/// A test process along with everything it starts, to be killed as a whole.
+#[cfg(unix)]
+struct ProcessTree {
+    group: libc::pid_t,
+}
+
+#[cfg(unix)]
+impl ProcessTree {
+    fn of(child: &process::Child) -> ProcessTree {
+        // the child leads its own process group, which its children join
+        ProcessTree {
+            group: child.id() as libc::pid_t,
+        }
+    }
+
+    fn kill(&self, _child: &mut process::Child) {
+        unsafe {
+            libc::kill(-self.group, libc::SIGKILL);
+        }
+    }
+}
+
+/// A test process along with everything it starts, to be killed as a whole.
+#[cfg(windows)]
+struct ProcessTree {
+    /// The job object holding the child, which its children join. Processes
+    /// the child starts before it's put in the job are missed.
+    job: Option<winapi::um::winnt::HANDLE>,
+}
+
+#[cfg(windows)]
+impl ProcessTree {
+    fn of(child: &process::Child) -> ProcessTree {
+        use std::mem;
+        use std::os::windows::io::AsRawHandle;
+        use std::ptr;
+        use winapi::shared::minwindef::{DWORD, LPVOID};
+        use winapi::um::handleapi::CloseHandle;
+        use winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject};
+        use winapi::um::winnt::{
+            JobObjectExtendedLimitInformation, HANDLE, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+            JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        };
+
+        unsafe {
+            let job = CreateJobObjectW(ptr::null_mut(), ptr::null());
+            if job.is_null() {
+                return ProcessTree { job: None };
+            }
+            // the job is closed when this process exits, even if it aborts,
+            // and closing it kills whatever is still running in it
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            let assigned = SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &mut info as *mut _ as LPVOID,
+                mem::size_of_val(&info) as DWORD,
+            ) != 0
+                && AssignProcessToJobObject(job, child.as_raw_handle() as HANDLE) != 0;
+            if !assigned {
+                CloseHandle(job);
+                return ProcessTree { job: None };
+            }
+            ProcessTree { job: Some(job) }
+        }
+    }
+
+    fn kill(&self, child: &mut process::Child) {
+        if let Some(job) = self.job {
+            unsafe {
+                if winapi::um::jobapi2::TerminateJobObject(job, 1) != 0 {
+                    return;
+                }
+            }
+        }
+        // taskkill walks the tree by parent process id instead, so it misses
+        // descendants whose parent already exited
+        let killed = Command::new("taskkill")
+            .args(&["/T", "/F", "/PID", &child.id().to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false);
+        if !killed {
+            let _ = child.kill();
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for ProcessTree {
+    fn drop(&mut self) {
+        // also ends whatever a test that finished left running
+        if let Some(job) = self.job {
+            unsafe {
+                winapi::um::handleapi::CloseHandle(job);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+struct ProcessTree;
+
+#[cfg(not(any(unix, windows)))]
+impl ProcessTree {
+    fn of(_child: &process::Child) -> ProcessTree {
+        ProcessTree
+    }
+
+    fn kill(&self, child: &mut process::Child) {
+        let _ = child.kill();
+    }
+}
+
+/// The exit status and output of a test run, as saved to a log file.
+fn output_log(output: &process::Output) -> String {
+    format!(
+        "{}\n\n--- stdout ---\n{}\n--- stderr ---\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr),
+    )
+}
+
+/// Interprets what a `--classifier` printed and how it exited.
+fn classifier_verdict(stdout: &str, status: Option<i32>) -> TestOutcome {
+    match stdout.split_whitespace().next() {
+        Some("regressed") => TestOutcome::Regressed,
+        Some("baseline") => TestOutcome::Baseline,
+        Some("unknown") => TestOutcome::Unknown,
+        _ => match status {
+            Some(0) => TestOutcome::Baseline,
+            Some(1) => TestOutcome::Regressed,
+            _ => TestOutcome::Unknown,
+        },
+    }
+}
+
+#[test]
+fn test_classifier_verdict() {
+    assert_eq!(classifier_verdict("regressed\n", Some(0)), TestOutcome::Regressed);
+    assert_eq!(classifier_verdict("  baseline", Some(1)), TestOutcome::Baseline);
+    assert_eq!(classifier_verdict("unknown", Some(0)), TestOutcome::Unknown);
+    assert_eq!(classifier_verdict("", Some(0)), TestOutcome::Baseline);
+    assert_eq!(classifier_verdict("looks bad", Some(1)), TestOutcome::Regressed);
+    assert_eq!(classifier_verdict("", Some(2)), TestOutcome::Unknown);
+    assert_eq!(classifier_verdict("", None), TestOutcome::Unknown);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_classify_large_output() {
+    // the classifier fills its stdout pipe before reading its stdin, which
+    // only works out if the output of the test is written to it meanwhile
+    let dir = TempDir::new("bisect-classify").unwrap();
+    let classifier = dir.path().join("classify.sh");
+    fs::write(
+        &classifier,
+        "#!/bin/sh\nhead -c 200000 /dev/zero | tr '\\0' ' '\ncat > /dev/null\necho regressed\n",
+    )
+    .unwrap();
+    Command::new("chmod").arg("+x").arg(&classifier).status().unwrap();
+
+    let t = Toolchain {
+        spec: ToolchainSpec::Nightly {
+            date: Date::from_utc(naive::NaiveDate::from_ymd(2019, 11, 1), Utc),
+        },
+        host: "x86_64-unknown-linux-gnu".to_string(),
+        std_targets: vec![],
+        name_prefix: String::new(),
+    };
+    let output = Command::new("sh")
+        .args(&["-c", "head -c 200000 /dev/zero; exit 101"])
+        .output()
+        .unwrap();
+    assert_eq!(t.classify(&classifier, &output), TestOutcome::Regressed);
+}
+
+/// Finds a `--target-dir` among the arguments passed through to cargo, if any.
+fn explicit_target_dir(cargo_args: &[OsString]) -> Option<PathBuf> {
+    let mut args = cargo_args.iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--target-dir" {
+            return args.next().map(PathBuf::from);
+        }
+        if arg.starts_with("--target-dir=") {
+            return Some(PathBuf::from(&arg["--target-dir=".len()..]));
+        }
+    }
+    None
+}
+
+/// Removes a cargo target directory ahead of a probe. Anything that doesn't
+/// look like a target directory, or that contains the test directory itself,
+/// is left alone.
+fn clean_target_dir(test_dir: &Path, target_dir: &Path) {
+    let target_dir = match target_dir.canonicalize() {
+        Ok(dir) => dir,
+        // nothing has been built there yet
+        Err(_) => return,
+    };
+    let test_dir = test_dir
+        .canonicalize()
+        .unwrap_or_else(|_| test_dir.to_path_buf());
+    let looks_like_target = ["CACHEDIR.TAG", ".rustc_info.json", "debug", "release"]
+        .iter()
+        .any(|entry| target_dir.join(entry).exists());
+
+    if test_dir.starts_with(&target_dir) || !looks_like_target {
+        eprintln!(
+            "not cleaning {}: it does not look like a cargo target directory",
+            target_dir.display()
+        );
+        return;
+    }
+
+    debug!("removing target directory {}", target_dir.display());
+    if let Err(err) = fs::remove_dir_all(&target_dir) {
+        eprintln!("failed to clean {}: {}", target_dir.display(), err);
+    }
+}
+
+struct Config {
+    args: Opts,
+    rustup_tmp_path: PathBuf,
+    toolchains_path: PathBuf,
+    /// The rustup toolchains directory when `toolchains_path` is elsewhere.
+    rustup_link_path: Option<PathBuf>,
+    /// The `--target`s, or just the host.
+    targets: Vec<String>,
+    is_commit: bool,
+    tool: Option<&'static Tool>,
+    /// Results of an earlier run loaded with `--resume`, by toolchain.
+    resumed: HashMap<String, Satisfies>,
+    /// The messages of the CI commits listed so far, by commit.
+    commit_messages: RefCell<HashMap<String, String>>,
+    /// How the test went with the start of the range, for the `--regress`
+    /// modes that compare against it.
+    baseline: RefCell<Baseline>,
+    /// Set while `--verify-regression` tests, to test in a fresh test
+    /// directory as if `--fresh-test-dir` was given.
+    verifying: Cell<bool>,
+    /// An error that ends the bisection, hit while testing a toolchain in a
+    /// predicate that can't return it, such as running out of disk space.
+    /// Once it is set no more toolchains are tested; see `check_fatal`.
+    fatal: RefCell<Option<Error>>,
+    stats: Stats,
+    _run_marker: RunMarker,
+}
+
+/// Marks this run as active for as long as it is alive, so that concurrent
+/// runs can notice each other.
+struct RunMarker {
+    path: PathBuf,
+}
+
+impl Drop for RunMarker {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Registers this run in `dir`, returning its marker along with whether any
+/// other live run is registered there. Markers of runs that are gone, e.g.
+/// because they were killed, are cleaned up.
+fn register_run(dir: &Path) -> io::Result<(RunMarker, bool)> {
+    fs::create_dir_all(dir)?;
+    let mut concurrent = false;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        match entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+            Some(pid) if process_alive(pid) => concurrent = true,
+            _ => {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+    let path = dir.join(process::id().to_string());
+    fs::write(&path, "")?;
+    Ok((RunMarker { path }, concurrent))
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    // signal 0 only checks whether the process exists; one of another user
+    // does, but may not be signalled
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return true;
+    }
+    io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(unix)]
+#[test]
+fn test_process_alive() {
+    assert!(process_alive(process::id()));
+    // pid 1 is always alive, and only root may signal it
+    assert!(process_alive(1));
+    let mut child = Command::new("true").spawn().unwrap();
+    let pid = child.id();
+    child.wait().unwrap();
+    assert!(!process_alive(pid));
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    // with /NH and CSV output, tasklist prints one `"name","pid",...` line per
+    // matching process and an informational message when there are none
+    Command::new("tasklist")
+        .args(&["/NH", "/FO", "CSV", "/FI", &format!("PID eq {}", pid)])
+        .stderr(Stdio::null())
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid))
+        })
+        .unwrap_or(true)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn process_alive(_pid: u32) -> bool {
+    // no way to tell; stale markers only cost an unneeded prefix
+    true
+}
+
+/// Where the results of each run are saved for `--resume`, in the test dir.
+const STATE_FILE: &str = ".cargo-bisect-rustc-state.json";
+
+/// Running totals reported by `--summary-stats` and `--count`, along with the
+/// result of every toolchain tested so far.
+struct Stats {
+    started: Instant,
+    probes: Cell<usize>,
+    unknowns: Cell<usize>,
+    /// Probes answered from `--resume` state or recorded outputs, without
+    /// downloading the toolchain.
+    cache_hits: Cell<usize>,
+    /// Probes answered `Unknown` because the toolchain couldn't be installed
+    /// or built, without running the test.
+    failed_installs: Cell<usize>,
+    probe_time: Cell<std::time::Duration>,
+    results: RefCell<HashMap<String, Satisfies>>,
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            started: Instant::now(),
+            probes: Cell::new(0),
+            unknowns: Cell::new(0),
+            cache_hits: Cell::new(0),
+            failed_installs: Cell::new(0),
+            probe_time: Cell::new(std::time::Duration::from_secs(0)),
+            results: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn record_probe(&self, t: &Toolchain, result: Satisfies, elapsed: std::time::Duration) {
+        self.results.borrow_mut().insert(t.to_string(), result);
+        self.probes.set(self.probes.get() + 1);
+        if result == Satisfies::Unknown {
+            self.unknowns.set(self.unknowns.get() + 1);
+        }
+        self.probe_time.set(self.probe_time.get() + elapsed);
+    }
+
+    fn record_cache_hit(&self) {
+        self.cache_hits.set(self.cache_hits.get() + 1);
+    }
+
+    fn record_failed_install(&self) {
+        self.failed_installs.set(self.failed_installs.get() + 1);
+    }
+
+    fn result_of(&self, t: &Toolchain) -> Option<Satisfies> {
+        self.results.borrow().get(&t.to_string()).cloned()
+    }
+
+    fn print_summary(&self) {
+        let probes = self.probes.get();
+        let average = if probes == 0 {
+            std::time::Duration::from_secs(0)
+        } else {
+            self.probe_time.get() / probes as u32
+        };
+
+        eprintln!("");
+        eprintln!("summary:");
+        eprintln!("  probes:          {} ({} unknown)", probes, self.unknowns.get());
+        eprintln!(
+            "  downloaded:      {}",
+            format_bytes(DOWNLOADED_BYTES.load(Ordering::Relaxed) as u64)
+        );
+        eprintln!("  wall-clock time: {}", format_duration(self.started.elapsed()));
+        eprintln!("  average probe:   {}", format_duration(average));
+    }
+
+    fn print_counts(&self) {
+        let probes = self.probes.get();
+        let cache_hits = self.cache_hits.get();
+        let failed_installs = self.failed_installs.get();
+        eprintln!(
+            "probed {} toolchains: {} downloaded and tested, {} from the cache, \
+             {} failed to install, {} unknown in all",
+            probes,
+            probes - cache_hits - failed_installs,
+            cache_hits,
+            failed_installs,
+            self.unknowns.get()
+        );
+    }
+
+    /// Prints whatever `--count` and `--summary-stats` asked for, once the
+    /// bisection is over.
+    fn print_requested(&self, args: &Opts) {
+        if args.count {
+            self.print_counts();
+        }
+        if args.summary_stats {
+            self.print_summary();
+        }
+    }
+}
+
+/// The host triple of the `rustc` on the `PATH`, for when this binary wasn't
+/// built with `HOST` set.
+fn rustc_host() -> Option<String> {
+    let output = Command::new("rustc").arg("-vV").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_rustc_host(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The `host:` line of `rustc -vV`.
+fn parse_rustc_host(verbose_version: &str) -> Option<String> {
+    verbose_version
+        .lines()
+        .find(|line| line.starts_with("host: "))
+        .map(|line| line["host: ".len()..].trim().to_string())
+        .filter(|host| !host.is_empty())
+}
+
+#[test]
+fn test_parse_rustc_host() {
+    let verbose_version = "rustc 1.40.0 (73528e339 2019-12-16)\n\
+                           binary: rustc\n\
+                           commit-hash: 73528e339aae0f17a15ffa49a8ac608f50c6cf14\n\
+                           commit-date: 2019-12-16\n\
+                           host: x86_64-unknown-linux-gnu\n\
+                           release: 1.40.0\n\
+                           LLVM version: 9.0\n";
+    assert_eq!(
+        parse_rustc_host(verbose_version),
+        Some("x86_64-unknown-linux-gnu".to_string())
+    );
+    assert_eq!(parse_rustc_host("rustc 1.40.0\nhost: \n"), None);
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn millis(duration: std::time::Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, s) => format!("{}h {}m {}s", h, m, s),
+    }
+}
+
+impl Config {
+    fn from_args(mut args: Opts) -> Result<Config, Error> {
+        if args.channel == ReleaseChannel::Beta && args.branch == "master" {
+            // the commits between two betas were merged into the beta branch
+            args.branch = "beta".to_string();
+        }
+
+        // the URLs are built by appending `/<path>`
+        while args.server.ends_with('/') {
+            args.server.pop();
+        }
+        while args.ci_server.ends_with('/') {
+            args.ci_server.pop();
+        }
+        if args.host == "unknown" {
+            match option_env!("HOST").filter(|host| !host.is_empty()) {
+                Some(host) => args.host = host.to_string(),
+                None => match rustc_host() {
+                    Some(host) => args.host = host,
+                    None => bail!(
+                        "Failed to auto-detect host triple and was not specified. Please provide it via --host"
+                    ),
+                },
+            }
+        }
+
+        if args.preserve_toolchains {
+            args.preserve = true;
+        }
+
+        if args.max_artifact_age < 1 {
+            bail!("--max-artifact-age must be at least 1 day");
+        }
+
+        if args.min_commit_interval == 0 {
+            bail!("--min-commit-interval must be at least 1");
+        }
+
+        if !args.include_rollups && args.min_commit_interval > 1 {
+            bail!("--bisect-include-rollups=false can't be combined with --min-commit-interval");
+        }
+
+        if args
+            .toolchain_link_name_prefix
+            .contains(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        {
+            bail!(
+                "--toolchain-link-name-prefix `{}` may only contain letters, digits, `-`, `_` and `.`",
+                args.toolchain_link_name_prefix
+            );
+        }
+
+        let tool = match args.tool {
+            Some(ref name) => Some(find_tool(name)?),
+            None => None,
+        };
+
+        // the test runs in the test directory, which may be a fresh copy
+        if let Some(file) = args.rustc_only.take() {
+            match fs::canonicalize(&file) {
+                Ok(file) => args.rustc_only = Some(file),
+                Err(err) => bail!("--rustc-only {}: {}", file.display(), err),
+            }
+        }
+
+        let targets = match args.target {
+            Some(ref list) => list
+                .split(',')
+                .map(|target| target.trim().to_string())
+                .filter(|target| !target.is_empty())
+                .collect(),
+            None => vec![args.host.clone()],
+        };
+        let mut args = args;
+
+        let mut toolchains_path = match env::var_os("RUSTUP_HOME") {
+            Some(h) => PathBuf::from(h),
+            None => {
+                let mut home = dirs::home_dir().ok_or_else(|| format_err!("Could not find home."))?;
+                home.push(".rustup");
+                home
+            }
+        };
+
+        // We will download and extract the tarballs into this directory before installing.
+        // Using `~/.rustup/tmp` instead of $TMPDIR ensures we could always perform installation by
+        // renaming instead of copying the whole directory; a custom `--install-tmp-dir` may need
+        // the copy.
+        let rustup_tmp_path = match args.install_tmp_dir {
+            Some(ref dir) => dir.clone(),
+            None => toolchains_path.join("tmp"),
+        };
+        if !rustup_tmp_path.exists() {
+            fs::create_dir_all(&rustup_tmp_path)?;
+        }
+
+        if let Some(dir) = args.output_dir.clone() {
+            fs::create_dir_all(&dir)?;
+            if args.keep_failed_logs.is_none() {
+                args.keep_failed_logs = Some(PathBuf::from("logs"));
+            }
+            for path in [
+                &mut args.keep_failed_logs,
+                &mut args.log_dir,
+                &mut args.collect_timings,
+                &mut args.output_cache,
+                &mut args.copy_artifacts_to,
+                &mut args.rerun_verbose,
+                &mut args.write_repro,
+            ] {
+                if let Some(ref mut path) = *path {
+                    if path.is_relative() {
+                        *path = dir.join(&path);
+                    }
+                }
+            }
+        }
+
+        let runs_path = toolchains_path.join("tmp").join("bisect-rustc-runs");
+
+        toolchains_path.push("toolchains");
+        if !toolchains_path.is_dir() {
+            bail!(
+                "`{}` is not a directory. Please install rustup.",
+                toolchains_path.display()
+            );
+        }
+
+        // Toolchains are installed in the artifacts dir and linked into rustup.
+        // The tmp dir is on the same volume so that installing is a rename.
+        let (toolchains_path, rustup_tmp_path, rustup_link_path) = match args.artifacts_dir {
+            Some(ref dir) => {
+                let installs = dir.join("toolchains");
+                let tmp = dir.join("tmp");
+                fs::create_dir_all(&installs)?;
+                fs::create_dir_all(&tmp)?;
+                (installs, tmp, Some(toolchains_path))
+            }
+            None => (toolchains_path, rustup_tmp_path, None),
+        };
+
+        // Concurrent runs would install, test and remove toolchains under the
+        // same names, so a run that isn't alone gets names of its own. Not so
+        // for --install, whose toolchains are meant to be used by their names.
+        let (run_marker, concurrent) = register_run(&runs_path)?;
+        if concurrent && args.install.is_empty() {
+            if args.toolchain_link_name_prefix.is_empty() {
+                args.toolchain_link_name_prefix = format!("run{}-", process::id());
+                eprintln!(
+                    "another bisection is running on this machine; installing toolchains \
+                     with the prefix `{}` to keep out of its way",
+                    args.toolchain_link_name_prefix
+                );
+            } else {
+                eprintln!(
+                    "another bisection is running on this machine; relying on the \
+                     --toolchain-link-name-prefix `{}` to keep out of its way",
+                    args.toolchain_link_name_prefix
+                );
+            }
+        }
+
+        if let Some(count) = args.since_commit_count {
+            let end = match args.end {
+                Some(Bound::Commit(ref sha)) => sha.clone(),
+                Some(Bound::Date(_)) => bail!("--since-commit-count needs a commit as --end"),
+                None => format!("origin/{}", args.branch),
+            };
+            let start = git::commit_before(&end, count, &args.branch)?;
+            eprintln!("starting {} commits before {}, at {}", count, end, start);
+            args.start = Some(Bound::Commit(start));
+        }
+
+        let is_commit = match (args.start.clone(), args.end.clone()) {
+            (Some(Bound::Commit(_)), Some(Bound::Commit(_)))
+            | (None, Some(Bound::Commit(_)))
+            | (Some(Bound::Commit(_)), None) => Some(true),
+
+            (Some(Bound::Date(_)), Some(Bound::Date(_)))
+            | (None, Some(Bound::Date(_)))
+            | (Some(Bound::Date(_)), None) => Some(false),
+
+            (None, None) => None,
+
+            // a date and a commit: the date stands for the commit of its
+            // nightly, and the commits in between are bisected
+            (Some(_), Some(_)) => Some(true),
+        };
+
+        if is_commit == Some(true) || args.by_commit {
+            let client = http_client(args.proxy.as_ref().map(|proxy| proxy.as_str()))?;
+            let server = args.server.clone();
+            let cache_dir = args.download_cache_dir();
+            for bound in [&mut args.start, &mut args.end] {
+                if let Some(Bound::Date(date)) = *bound {
+                    eprintln!("finding the commit that corresponds to {}", date.format("%Y-%m-%d"));
+                    let commit = Bound::Date(date).as_commit(
+                        &client,
+                        &server,
+                        args.channel,
+                        args.download_retries,
+                        cache_dir.as_ref().map(|dir| dir.as_path()),
+                    )?;
+                    *bound = Some(commit);
+                }
+            }
+        }
+
+        Ok(Config {
+            is_commit: args.by_commit || is_commit == Some(true),
+            args,
+            targets,
+            toolchains_path,
+            rustup_tmp_path,
+            rustup_link_path,
+            tool,
+            resumed: HashMap::new(),
+            commit_messages: RefCell::new(HashMap::new()),
+            baseline: RefCell::new(Baseline::default()),
+            verifying: Cell::new(false),
+            fatal: RefCell::new(None),
+            stats: Stats::new(),
+            _run_marker: run_marker,
+        })
+    }
+
+    /// The targets to install the standard library for: the host and the
+    /// `--target`s.
+    fn std_targets(&self) -> Vec<String> {
+        let mut targets = self.targets.clone();
+        targets.push(self.args.host.clone());
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+
+    /// The targets to run the test for. With a single target, that is left
+    /// to the test command, as it always has been.
+    fn test_targets(&self) -> Vec<Option<&str>> {
+        if self.targets.len() < 2 {
+            vec![None]
+        } else {
+            self.targets.iter().map(|target| Some(target.as_str())).collect()
+        }
+    }
+
+    /// How long a test may run, and what it counts as when it runs longer.
+    /// `--script-timeout` applies to scripts instead of `--timeout`.
+    fn test_timeout(&self) -> Option<(u64, TestOutcome)> {
+        match (&self.args.script, self.args.script_timeout) {
+            (&Some(_), Some(secs)) => Some((secs, self.args.script_timeout_outcome)),
+            _ => self.args.timeout.map(|secs| (secs, self.args.timeout_outcome)),
+        }
+    }
+
+    /// Where downloaded tarballs are kept, if anywhere.
+    fn download_cache_dir(&self) -> Option<PathBuf> {
+        self.args.download_cache_dir()
+    }
+
+    /// The toolchain of the CI build of `commit`. Commits of the beta branch
+    /// are built as beta.
+    fn ci_spec(&self, commit: String) -> ToolchainSpec {
+        let channel = if self.args.branch == "beta" {
+            ReleaseChannel::Beta
+        } else {
+            self.args.channel
+        };
+        ToolchainSpec::Ci {
+            commit,
+            alt: self.args.alt,
+            channel,
+        }
+    }
+
+    /// The toolchain of the `--channel` build published on `date`.
+    fn dated_spec(&self, date: Date<Utc>) -> ToolchainSpec {
+        ToolchainSpec::dated(self.args.channel, date)
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.args.test_dir.join(STATE_FILE)
+    }
+
+    /// What a saved result depends on besides the toolchain, so that results
+    /// of a run testing something else aren't resumed.
+    fn state_fingerprint(&self) -> String {
+        format!(
+            "{} judged by {:?} / {:?} looking for a {:?} for {} on {}",
+            self.test_command(),
+            self.args.regress,
+            self.args.classifier,
+            self.args.bisect_direction,
+            self.targets.join(","),
+            self.args.host
+        )
+    }
+
+    /// Loads the results saved by an earlier run for `--resume`.
+    fn load_state(&mut self) -> Result<(), Error> {
+        let path = self.state_path();
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(_) => {
+                eprintln!("no saved state in {}, starting afresh", path.display());
+                return Ok(());
+            }
+        };
+        let state: serde_json::Value = serde_json::from_slice(&contents)
+            .map_err(|err| format_err!("failed to parse {}: {}", path.display(), err))?;
+        if state["fingerprint"].as_str() != Some(self.state_fingerprint().as_str()) {
+            eprintln!(
+                "warning: {} was saved by a run testing something else; starting afresh",
+                path.display()
+            );
+            return Ok(());
+        }
+        if let Some(results) = state["results"].as_object() {
+            for (toolchain, result) in results {
+                let result = match result.as_str() {
+                    Some("Yes") => Satisfies::Yes,
+                    Some("No") => Satisfies::No,
+                    Some("Unknown") => Satisfies::Unknown,
+                    _ => continue,
+                };
+                self.resumed.insert(toolchain.clone(), result);
+            }
+        }
+        eprintln!("resuming with {} results from {}", self.resumed.len(), path.display());
+        Ok(())
+    }
+
+    /// Returns the error that ended testing toolchains, if any.
+    fn check_fatal(&self) -> Result<(), Error> {
+        match self.fatal.borrow_mut().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Saves every result so far, so that an interrupted run can be resumed.
+    fn save_state(&self) {
+        let mut results = serde_json::Map::new();
+        let stats_results = self.stats.results.borrow();
+        for (toolchain, result) in self.resumed.iter().chain(stats_results.iter()) {
+            results.insert(toolchain.clone(), json!(result.to_string()));
+        }
+        let state = json!({
+            "fingerprint": self.state_fingerprint(),
+            "results": results,
+        });
+        let path = self.state_path();
+        if let Err(err) = fs::write(&path, state.to_string()) {
+            eprintln!("warning: failed to save the bisection state to {}: {}", path.display(), err);
+        }
+    }
+
+    /// The result an earlier run got for `t`, with `--resume`.
+    fn resumed_outcome(&self, t: &Toolchain) -> Option<TestOutcome> {
+        let outcome = match *self.resumed.get(&t.to_string())? {
+            Satisfies::Yes => TestOutcome::Regressed,
+            Satisfies::No => TestOutcome::Baseline,
+            Satisfies::Unknown => TestOutcome::Unknown,
+        };
+        eprintln!("{} was tested by the resumed run: {:?}", t, outcome);
+        Some(outcome)
+    }
+
+    /// The head of the branch being bisected, used when no `--end` is given.
+    fn default_end_ref(&self) -> String {
+        format!("origin/{}", self.args.branch)
+    }
+
+    fn output_processing_mode(&self) -> &OutputProcessingMode {
+        &self.args.regress
+    }
+
+    /// Whether the test command's output needs to be captured rather than
+    /// just inherited or discarded.
+    fn must_capture_output(&self) -> bool {
+        self.output_processing_mode().must_process_stderr()
+            || self.args.keep_failed_logs.is_some()
+            || self.args.log_dir.is_some()
+            || self.args.classifier.is_some()
+            || self.args.output_cache.is_some()
+    }
+
+    /// The arguments cargo is run with when there is no `--script`.
+    fn cargo_args(&self) -> Vec<OsString> {
+        let mut args = if self.args.cargo_args.is_empty() {
+            vec![OsString::from("build")]
+        } else {
+            self.args.cargo_args.clone()
+        };
+        if self.output_processing_mode().wants_json_diagnostics() {
+            // keep it ahead of any arguments meant for the test binary
+            let pos = args.iter().position(|a| a == "--").unwrap_or(args.len());
+            args.insert(pos, OsString::from("--message-format=json"));
+        }
+        if self.args.collect_timings.is_some() {
+            let pos = args.iter().position(|a| a == "--").unwrap_or(args.len());
+            args.insert(pos, OsString::from("-Ztimings"));
+        }
+        args
+    }
+
+    /// `RUSTFLAGS` for the test and setup scripts: the `--rustflags` after
+    /// any already set in the environment.
+    fn rustflags(&self) -> Option<String> {
+        let flags = self.args.rustflags.as_ref()?;
+        match env::var("RUSTFLAGS") {
+            Ok(ref existing) if !existing.trim().is_empty() => {
+                Some(format!("{} {}", existing, flags))
+            }
+            _ => Some(flags.clone()),
+        }
+    }
+
+    /// The arguments rustc is run with for `--rustc-only`.
+    fn rustc_args(&self, file: &Path) -> Vec<OsString> {
+        let mut args = vec![file.as_os_str().to_owned()];
+        if let Some(ref flags) = self.args.rustflags {
+            args.extend(flags.split_whitespace().map(OsString::from));
+        }
+        args.extend(self.args.cargo_args.iter().cloned());
+        if self.output_processing_mode().wants_json_diagnostics() {
+            args.push(OsString::from("--error-format=json"));
+        }
+        args
+    }
+
+    /// Describes what is run for each test, so that recorded outputs of a
+    /// different command aren't reused.
+    fn test_command(&self) -> String {
+        let command = match (&self.args.script, self.tool, &self.args.rustc_only) {
+            (&Some(ref script), _, _) => format!("script {}", script.display()),
+            (&None, Some(tool), _) => format!("{:?} {:?}", tool.command, self.args.cargo_args),
+            (&None, None, &Some(ref file)) => format!("rustc {:?}", self.rustc_args(file)),
+            (&None, None, &None) => format!("cargo {:?}", self.cargo_args()),
+        };
+        format!(
+            "{} in {} after {:?} with RUSTFLAGS {:?}",
+            command,
+            self.args.test_dir.display(),
+            self.args.setup_scripts,
+            self.rustflags()
+        )
+    }
+
+    fn default_outcome_of_output(
+        &self,
+        output: &process::Output,
+        elapsed: Option<std::time::Duration>,
+    ) -> TestOutcome {
+        let status = output.status;
+        let stdout_utf8 = String::from_utf8_lossy(&output.stdout);
+        let stderr_utf8 = String::from_utf8_lossy(&output.stderr);
+        debug!(
+            "status: {:?} stdout: {:?} stderr: {:?}",
+            status, stdout_utf8, stderr_utf8
+        );
+
+        let regressed = match self.output_processing_mode().regressed(
+            status,
+            &stdout_utf8,
+            &stderr_utf8,
+            elapsed,
+            &self.baseline.borrow(),
+        ) {
+            Some(regressed) => regressed,
+            None => return TestOutcome::Unknown,
+        };
+        let outcome = if regressed {
+            TestOutcome::Regressed
+        } else {
+            TestOutcome::Baseline
+        };
+        debug!("default_outcome_of_output: {:?}", outcome);
+        outcome
+    }
+}
+
+/// Whether `stderr` reports a panic with `message` in it. The message follows
+/// `panicked at` either on the same line or, in newer versions, the next one.
+fn saw_panic_message(stderr: &str, message: &str) -> bool {
+    let lines = stderr.lines().collect::<Vec<_>>();
+    lines.iter().enumerate().any(|(i, line)| {
+        line.contains("panicked at")
+            && (line.contains(message) || lines.get(i + 1).map_or(false, |l| l.contains(message)))
+    })
+}
+
+/// Whether `stderr` reports a panic of rustc itself, which runs the compiler
+/// on a thread named `rustc`, rather than of a program it compiled.
+fn saw_rustc_panic(stderr: &str) -> bool {
+    stderr.lines().any(|line| line.contains("thread 'rustc' panicked at"))
+}
+
+/// Whether the test command itself was killed by a signal.
+#[cfg(unix)]
+fn crashed(status: process::ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().is_some()
+}
+
+/// Whether the test command itself died of an exception, whose
+/// `NTSTATUS` code is its exit code: error codes have both top bits set.
+#[cfg(windows)]
+fn crashed(status: process::ExitStatus) -> bool {
+    status
+        .code()
+        .map_or(false, |code| code as u32 & 0xC000_0000 == 0xC000_0000)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn crashed(_status: process::ExitStatus) -> bool {
+    false
+}
+
+/// Whether cargo reports that a process it ran, e.g. rustc, crashed; cargo
+/// itself then just exits with an error.
+fn saw_crash_report(stderr: &str) -> bool {
+    stderr.lines().any(|line| {
+        line.contains("process didn't exit successfully")
+            && (line.contains("(signal: ") || line.contains("(exit code: 0xc0000"))
+    })
+}
+
+#[test]
+fn test_saw_crash_report() {
+    assert!(saw_crash_report(
+        "error: could not compile `foo`\n\nCaused by:\n  process didn't exit successfully: \
+         `rustc --crate-name foo src/lib.rs` (signal: 11, SIGSEGV: invalid memory reference)"
+    ));
+    assert!(saw_crash_report(
+        "  process didn't exit successfully: `rustc.exe src/lib.rs` \
+         (exit code: 0xc0000005, STATUS_ACCESS_VIOLATION)"
+    ));
+    assert!(!saw_crash_report(
+        "  process didn't exit successfully: `rustc src/lib.rs` (exit status: 1)"
+    ));
+}
+
+#[test]
+fn test_saw_rustc_panic() {
+    assert!(saw_rustc_panic(
+        "thread 'rustc' panicked at 'index out of bounds', src/librustc/ty/mod.rs:1:1"
+    ));
+    assert!(!saw_rustc_panic(
+        "thread 'main' panicked at 'index out of bounds', src/main.rs:1:1"
+    ));
+    assert!(!saw_rustc_panic("error: internal compiler error: unexpected panic"));
+}
+
+#[test]
+fn test_saw_panic_message() {
+    let old = "thread 'main' panicked at 'attempt to add with overflow', src/main.rs:3:5";
+    let new = "thread 'main' panicked at src/main.rs:3:5:\nattempt to add with overflow";
+    assert!(saw_panic_message(old, "add with overflow"));
+    assert!(saw_panic_message(new, "add with overflow"));
+    assert!(!saw_panic_message(old, "subtract"));
+    assert!(!saw_panic_message("error: add with overflow", "add with overflow"));
+}
+
+/// Rejects bounds that can't make a meaningful bisection: dates after
+/// `today`, most likely typos, and date ranges that are empty.
+fn check_bounds(start: &Option<Bound>, end: &Option<Bound>, today: Date<Utc>) -> Result<(), Error> {
+    for &(flag, bound) in &[("--start", start), ("--end", end)] {
+        if let Some(Bound::Date(date)) = *bound {
+            if date > today {
+                bail!(
+                    "{} {} is in the future; the latest possible nightly is today's, {}",
+                    flag,
+                    date.format("%Y-%m-%d"),
+                    today.format("%Y-%m-%d")
+                );
+            }
+        }
+    }
+
+    match (&start, &end) {
+        (Some(Bound::Date(start)), Some(Bound::Date(end))) if end < start => {
+            bail!(
+                "end should be after start, got start: {:?} and end {:?}",
+		start,
+		end
+            );
+	},
+        (Some(Bound::Date(start)), Some(Bound::Date(end))) if end == start => {
+            bail!(
+                "start and end are both {}; nothing to bisect. Try widening the range.",
+                start.format("%Y-%m-%d")
+            );
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_check_bounds() {
+    let date = |s: &str| Some(Bound::Date(Date::from_utc(s.parse().unwrap(), Utc)));
+    let today = Date::from_utc("2019-11-20".parse().unwrap(), Utc);
+    assert!(check_bounds(&date("2019-11-01"), &date("2019-11-20"), today).is_ok());
+    assert!(check_bounds(&date("2019-11-01"), &None, today).is_ok());
+    // a future start date, e.g. the wrong year
+    assert!(check_bounds(&date("2020-11-01"), &None, today).is_err());
+    assert!(check_bounds(&date("2019-11-01"), &date("2019-11-21"), today).is_err());
+    // start == end
+    assert!(check_bounds(&date("2019-11-01"), &date("2019-11-01"), today).is_err());
+    assert!(check_bounds(&date("2019-11-02"), &date("2019-11-01"), today).is_err());
+}
+
+fn run() -> Result<(), Error> {
+    let args = env::args_os().filter(|a| a != "bisect-rustc");
+    let args = Opts::from_iter(args);
+    init_logger(args.log_format)?;
+    if args.list_regress_modes {
+        list_regress_modes();
+        return Ok(());
+    }
+    check_bounds(&args.start, &args.end, chrono::Utc::now().date())?;
+    let mut cfg = Config::from_args(args)?;
+    if cfg.args.resume {
+        cfg.load_state()?;
+    }
+
+    let client = http_client(cfg.args.proxy.as_ref().map(|proxy| proxy.as_str()))?;
+
+    if !cfg.args.install.is_empty() {
+        install_all(&cfg, &client)
+    } else if cfg.args.estimate {
+        estimate(&cfg, &client)
+    } else if cfg.args.dry_run {
+        dry_run(&cfg, &client)
+    } else {
+        bisect(&cfg, &client)
+    }
+}
+
+/// Installs each of the `--install` toolchains, reporting at the end which of
+/// them failed.
+fn install_all(cfg: &Config, client: &Client) -> Result<(), Error> {
+    if cfg.args.install.len() == 1 {
+        return install(cfg, client, &cfg.args.install[0]);
+    }
+
+    let installed = cfg
+        .args
+        .install
+        .iter()
+        .map(|bound| match install(cfg, client, bound) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("failed to install {}: {}", bound, err);
+                false
+            }
+        })
+        .collect::<Vec<_>>();
+
+    eprintln!("");
+    for (bound, &ok) in cfg.args.install.iter().zip(&installed) {
+        eprintln!("{}: {}", bound, if ok { "installed" } else { "failed" });
+    }
+    let failed = installed.iter().filter(|&&ok| !ok).count();
+    if failed > 0 {
+        bail!("failed to install {} of {} toolchains", failed, installed.len());
+    }
+    Ok(())
+}
+
+fn install(cfg: &Config, client: &Client, bound: &Bound) -> Result<(), Error> {
+    let (t, dl_params) = match *bound {
+        Bound::Commit(ref sha) => {
+            let sha = git::expand_commit(sha, &cfg.args.branch)?;
+            let t = Toolchain {
+                spec: cfg.ci_spec(sha.clone()),
+                host: cfg.args.host.clone(),
+                name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+                std_targets: cfg.std_targets(),
+            };
+            (t, DownloadParams::for_ci(cfg))
+        }
+        Bound::Date(date) => {
+            let t = Toolchain {
+                spec: cfg.dated_spec(date),
+                host: cfg.args.host.clone(),
+                name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+                std_targets: cfg.std_targets(),
+            };
+            (t, DownloadParams::for_nightly(cfg))
+        }
+    };
+    if let Some(ref dir) = cfg.args.extract_only {
+        let dest = dir.join(t.rustup_name());
+        if dest.exists() {
+            bail!("{} already exists", dest.display());
+        }
+        fs::create_dir_all(dir)?;
+        t.extract_to(client, &dl_params, &dest)?;
+        eprintln!("extracted {} into {}", t, dest.display());
+        return Ok(());
+    }
+    t.install(client, &dl_params)?;
+    verify_installed(&t)
+}
+
+/// Checks that rustup can actually find a freshly installed toolchain, and
+/// tells the user how to use it.
+fn verify_installed(t: &Toolchain) -> Result<(), Error> {
+    if t.is_current_nightly() {
+        eprintln!("{} is the currently installed default nightly", t);
+        return Ok(());
+    }
+
+    let name = t.rustup_name();
+    let output = Command::new("rustup")
+        .args(&["run", &name, "rustc", "--version"])
+        .output()
+        .map_err(|err| format_err!("failed to run rustup to verify {}: {}", name, err))?;
+    if !output.status.success() {
+        bail!(
+            "installed {}, but rustup could not run it:\n{}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    eprintln!(
+        "installed {}: {}",
+        name,
+        String::from_utf8_lossy(&output.stdout).trim()
+    );
+    eprintln!("use it with `cargo +{} build`", name);
+    Ok(())
+}
+
+/// Roughly how many CI builds land in a day, and so fall between two nightlies.
+const CI_BUILDS_PER_NIGHTLY: usize = 15;
+
+/// Prints a rough prediction of how long the bisection will take, from the
+/// size of the range, a sample of the download speed and a guess at how long
+/// the test takes.
+fn estimate(cfg: &Config, client: &Client) -> Result<(), Error> {
+    let (candidates, sample, dl_params) = if cfg.is_commit {
+        let (start, end) = ci_bounds(cfg);
+        let toolchains = ci_toolchains_between(cfg, &start, &end)?;
+        let sample = toolchains.last().unwrap().clone();
+        (toolchains.len(), sample, DownloadParams::for_ci(cfg))
+    } else {
+        let end = get_end_date(cfg, client)?;
+        let days = match cfg.args.start {
+            Some(Bound::Date(start)) => (end - start).num_days() as usize + 1,
+            _ => {
+                eprintln!(
+                    "note: without --start, the search for a good nightly takes \
+                     additional probes that aren't counted here"
+                );
+                2
+            }
+        };
+        let sample = Toolchain {
+            spec: cfg.dated_spec(end),
+            host: cfg.args.host.clone(),
+            name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+            std_targets: cfg.std_targets(),
+        };
+        (days, sample, DownloadParams::for_nightly(cfg))
+    };
+
+    let mut probes = bisection_probes(candidates);
+    if !cfg.is_commit {
+        // and then the commits of the regressed nightly
+        probes += bisection_probes(CI_BUILDS_PER_NIGHTLY);
+    }
+
+    eprintln!("measuring the download speed with {}", sample);
+    let (bytes_per_sec, toolchain_size) = sample_download(client, &sample, &dl_params)?;
+    let download_secs = toolchain_size as f64 / bytes_per_sec;
+    let probe_secs = download_secs + cfg.args.estimate_test_secs as f64;
+    let total = std::time::Duration::from_secs((probe_secs * probes as f64) as u64);
+
+    eprintln!("estimate for bisecting {} toolchains:", candidates);
+    eprintln!("  probes:   ~{}", probes);
+    eprintln!(
+        "  download: ~{} per toolchain at ~{}/s, ~{} each",
+        format_bytes(toolchain_size),
+        format_bytes(bytes_per_sec as u64),
+        format_duration(std::time::Duration::from_secs(download_secs as u64)),
+    );
+    eprintln!(
+        "  test:     {} per toolchain (a guess, see --estimate-test-secs)",
+        format_duration(std::time::Duration::from_secs(cfg.args.estimate_test_secs)),
+    );
+    eprintln!("  total:    ~{}", format_duration(total));
+    eprintln!(
+        "this is only a rough estimate: test durations, download speeds and \
+         unavailable toolchains can change it a lot"
+    );
+
+    Ok(())
+}
+
+/// About how many toolchains `least_satisfying` tests in a range of `len`:
+/// both ends, then a binary search of what lies between them.
+fn bisection_probes(len: usize) -> usize {
+    let mut steps = 0;
+    while (1 << steps) < len.saturating_sub(1) {
+        steps += 1;
+    }
+    cmp::min(2 + steps, len)
+}
+
+#[test]
+fn test_bisection_probes() {
+    assert_eq!(bisection_probes(1), 1);
+    assert_eq!(bisection_probes(2), 2);
+    assert_eq!(bisection_probes(3), 3);
+    assert_eq!(bisection_probes(17), 6);
+    assert_eq!(bisection_probes(18), 7);
+}
+
+/// Times the download of the start of a toolchain's rustc tarball, returning
+/// the observed speed in bytes per second along with the total size of the
+/// toolchain's tarballs.
+fn sample_download(
+    client: &Client,
+    t: &Toolchain,
+    dl_params: &DownloadParams,
+) -> Result<(f64, u64), Error> {
+    const SAMPLE_BYTES: u64 = 4 * 1024 * 1024;
+
+    let (size, urls) = download_size(client, t, dl_params)?;
+    let url = match urls.first() {
+        Some(url) => url,
+        None => bail!("could not find the tarballs of {} to measure the download speed", t),
+    };
+    let source: Box<dyn Read> = match local_path(url) {
+        Some(path) => Box::new(fs::File::open(path)?),
+        None => Box::new(client.get(url).send()?.error_for_status()?),
+    };
+    let started = Instant::now();
+    let read = io::copy(&mut source.take(SAMPLE_BYTES), &mut io::sink())?;
+    let elapsed = started.elapsed();
+    let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+    Ok((read as f64 / secs.max(0.001), size))
+}
+
+/// The total size of a toolchain's tarballs as announced by the server, along
+/// with the URLs of the ones that exist, without downloading them.
+fn download_size(
+    client: &Client,
+    t: &Toolchain,
+    dl_params: &DownloadParams,
+) -> Result<(u64, Vec<String>), Error> {
+    let mut size = 0;
+    let mut urls = Vec::new();
+    for component in t.components(dl_params, Path::new("")) {
+        for ext in &["xz", "gz"] {
+            let url = format!("{}.{}", component.url, ext);
+            if let Some(path) = local_path(&url) {
+                if let Ok(metadata) = fs::metadata(path) {
+                    size += metadata.len();
+                    urls.push(url);
+                    break;
+                }
+                continue;
+            }
+            let response = client.head(&url).send()?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                continue;
+            }
+            size += content_length(&response.error_for_status()?);
+            urls.push(url);
+            break;
+        }
+    }
+    Ok((size, urls))
+}
+
+/// Above this estimated total download size, a bisection asks for confirmation
+/// unless `--yes` is given.
+const CONFIRM_DOWNLOAD_SIZE: u64 = 1 << 30;
+
+/// Prints how much bisecting `toolchains` will probably download, and unless
+/// `--yes` is given, asks the user to confirm if that is a lot.
+fn confirm_download(
+    cfg: &Config,
+    client: &Client,
+    toolchains: &[Toolchain],
+    dl_params: &DownloadParams,
+) -> Result<(), Error> {
+    let t = match toolchains.last() {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+    let size = match download_size(client, t, dl_params) {
+        Ok((size, _)) if size > 0 => size,
+        // unknown, e.g. the server doesn't say; not worth stopping for
+        _ => return Ok(()),
+    };
+    let probes = bisection_probes(toolchains.len());
+    let total = size * probes as u64;
+    eprintln!(
+        "testing about {} of these {} toolchains will download about {} ({} each)",
+        probes,
+        toolchains.len(),
+        format_bytes(total),
+        format_bytes(size)
+    );
+    if cfg.args.yes || total < CONFIRM_DOWNLOAD_SIZE {
+        return Ok(());
+    }
+    if !(atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stderr)) {
+        eprintln!("note: not running in a terminal, so going ahead without asking");
+        return Ok(());
+    }
+    let go_ahead = Confirmation::new()
+        .with_text("continue?")
+        .default(true)
+        .interact()
+        .unwrap_or(false);
+    if !go_ahead {
+        bail!("stopped before downloading; pass --yes to skip this question");
+    }
+    Ok(())
+}
+
+/// Prints the toolchains a bisection would choose from, for `--dry-run`.
+fn dry_run(cfg: &Config, client: &Client) -> Result<(), Error> {
+    let (toolchains, dl_params) = if cfg.is_commit {
+        let (start, end) = ci_bounds(cfg);
+        eprintln!("listing ci builds from {} to {}", start, end);
+        (ci_toolchains_between(cfg, &start, &end)?, DownloadParams::for_ci(cfg))
+    } else {
+        let toolchains = if let Some(ref path) = cfg.args.nightlies_from_file {
+            listed_nightlies(cfg, path)?
+        } else {
+            let end = get_end_date(cfg, client)?;
+            let start = match cfg.args.start {
+                Some(Bound::Date(start)) => start,
+                _ => {
+                    eprintln!(
+                        "note: without --start, good nightlies are searched for backwards \
+                         from {}, so only that nightly is listed",
+                        end.format("%Y-%m-%d")
+                    );
+                    end
+                }
+            };
+            toolchains_between(
+                cfg,
+                cfg.dated_spec(start),
+                cfg.dated_spec(end),
+            )
+        };
+        (toolchains, DownloadParams::for_nightly(cfg))
+    };
+
+    for t in &toolchains {
+        println!("{}", t);
+    }
+    let probes = bisection_probes(toolchains.len());
+    eprintln!(
+        "{} toolchains in the range, of which a bisection tests about {}",
+        toolchains.len(),
+        probes
+    );
+    if !cfg.is_commit {
+        eprintln!(
+            "then about {} ci builds of the regressed nightly",
+            bisection_probes(CI_BUILDS_PER_NIGHTLY)
+        );
+    }
+
+    if let Some(t) = toolchains.last() {
+        match download_size(client, t, &dl_params) {
+            Ok((0, _)) => eprintln!("the server doesn't announce the size of {}", t),
+            Ok((size, _)) => eprintln!(
+                "downloading {} takes {}, so about {} for the bisection",
+                t,
+                format_bytes(size),
+                format_bytes(size * probes as u64)
+            ),
+            Err(err) => eprintln!("could not determine the download size: {}", err),
+        }
+    }
+
+    Ok(())
+}
+
+fn bisect(cfg: &Config, client: &Client) -> Result<(), Error> {
+    let mut ambiguous = false;
+    let mut check_ambiguity = |bisection_result: &BisectionResult, found: bool| {
+        if let Some(reason) = ambiguity(cfg, bisection_result, found) {
+            eprintln!("note: {}", reason);
+            ambiguous = true;
+        }
+        ambiguous
+    };
+
+    if let Some(ref checkout) = cfg.args.local_build {
+        return bisect_local(cfg, checkout);
+    }
+
+    if cfg.output_processing_mode().needs_baseline() {
+        record_baseline(cfg, client)?;
+    }
+
+    if cfg.args.all_transitions {
+        return find_all_transitions(cfg, client);
+    }
+
+    if let Some(runs) = cfg.args.validate {
+        validate_test(cfg, client, runs)?;
+    }
+
+    if cfg.is_commit {
+        let bisection_result = bisect_ci(&cfg, &client)?;
+        let found = print_results(cfg, client, &bisection_result);
+        cfg.check_fatal()?;
+        let ambiguous = check_ambiguity(&bisection_result, found);
+        eprintln!("confidence: {}", Confidence::of(cfg, ambiguous));
+        if found {
+            // an unrefined result names a range, not the commit to blame
+            if !bisection_result.unrefined {
+                let regressed = &bisection_result.searched[bisection_result.found];
+                for link in pull_request_links(cfg, regressed) {
+                    eprintln!("pull request: {}", link);
+                }
+                write_repro(cfg, regressed)?;
+            }
+            rerun_decisive(cfg, client, &bisection_result);
+            verify_regression(cfg, client, &bisection_result);
+            copy_artifacts(cfg, client, &bisection_result)?;
+        }
+    } else {
+        let nightly_bisection_result = bisect_nightlies(&cfg, &client)?;
+        let found = print_results(cfg, client, &nightly_bisection_result);
+        cfg.check_fatal()?;
+        check_ambiguity(&nightly_bisection_result, found);
+        let nightly_regression = &nightly_bisection_result.searched[nightly_bisection_result.found];
+        // usually the day before, but the searched nightlies need not be contiguous
+        let previous_nightly = &nightly_bisection_result.searched[nightly_bisection_result.found - 1];
+
+        if let (Some(date), Some(previous_date)) =
+            (nightly_regression.spec.date(), previous_nightly.spec.date())
+        {
+            let channel = cfg.args.channel;
+            let server = &cfg.args.server;
+            let retries = cfg.args.download_retries;
+            let cache_dir = cfg.download_cache_dir();
+            let cache_dir = cache_dir.as_ref().map(|dir| dir.as_path());
+            if let Bound::Commit(bad_commit) =
+                Bound::Date(date).as_commit(client, server, channel, retries, cache_dir)?
+            {
+                if let Bound::Commit(working_commit) =
+                    Bound::Date(previous_date).as_commit(client, server, channel, retries, cache_dir)?
+                {
+                    eprintln!(
+                        "looking for regression commit between {} and {}",
+                        date.format("%Y-%m-%d"),
+                        previous_date.format("%Y-%m-%d"),
+                    );
+
+                    let ci_bisection_result = bisect_ci_between(cfg, client, &working_commit, &bad_commit)?;
+                    let found = print_results(cfg, client, &ci_bisection_result);
+                    cfg.check_fatal()?;
+                    let ambiguous = check_ambiguity(&ci_bisection_result, found);
+                    print_final_report(
+                        cfg,
+                        &nightly_bisection_result,
+                        &ci_bisection_result,
+                        Confidence::of(cfg, ambiguous),
+                    );
+                    if found {
+                        if !ci_bisection_result.unrefined {
+                            write_repro(cfg, &ci_bisection_result.searched[ci_bisection_result.found])?;
+                        }
+                        rerun_decisive(cfg, client, &ci_bisection_result);
+                        verify_regression(cfg, client, &ci_bisection_result);
+                        copy_artifacts(cfg, client, &ci_bisection_result)?;
+                    }
+                }
+            }
+        }
+    }
+
+    cfg.stats.print_requested(&cfg.args);
+
+    if ambiguous && cfg.args.fail_on_ambiguous {
+        eprintln!("error: the bisection did not narrow the regression down to a single toolchain");
+        return Err(ExitError(2).into());
+    }
+
+    Ok(())
+}
+
+/// Tests the toolchain at the start of the range, which the others are
+/// compared with for `--regress=slower-than:<FACTOR>x` and `new-warning`.
+fn record_baseline(cfg: &Config, client: &Client) -> Result<(), Error> {
+    let (spec, dl_spec) = if cfg.is_commit {
+        let (start, _) = ci_bounds(cfg);
+        let commit = git::expand_commit(&start, &cfg.args.branch)?;
+        let spec = cfg.ci_spec(commit);
+        (spec, DownloadParams::for_ci(cfg))
+    } else {
+        match cfg.args.start {
+            Some(Bound::Date(date)) => (cfg.dated_spec(date), DownloadParams::for_nightly(cfg)),
+            _ => bail!(
+                "--regress=slower-than:<FACTOR>x and new-warning need a --start date to \
+                 compare nightlies against"
+            ),
+        }
+    };
+    let t = Toolchain {
+        spec,
+        host: cfg.args.host.clone(),
+        name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+        std_targets: cfg.std_targets(),
+    };
+
+    eprintln!("testing {}, the start of the range, to compare the others with", t);
+    t.install(client, &dl_spec)?;
+    let run = t.run_test(cfg, false, None);
+    if !cfg.args.preserve {
+        let _ = t.remove(&dl_spec);
+    }
+    let (output, elapsed) = run?;
+
+    let mut baseline = cfg.baseline.borrow_mut();
+    let timed = cfg
+        .output_processing_mode()
+        .time_limits()
+        .iter()
+        .any(|limit| match *limit {
+            TimeLimit::TimesBaseline(_) => true,
+            TimeLimit::Millis(_) => false,
+        });
+    if timed {
+        if !output.status.success() {
+            bail!("the test failed with {}, so it can't be timed", t);
+        }
+        eprintln!("the test took {}ms with {}", millis(elapsed), t);
+        baseline.time = Some(elapsed);
+    }
+    let warnings = warning_lines(&String::from_utf8_lossy(&output.stderr));
+    eprintln!("the test printed {} distinct warnings with {}", warnings.len(), t);
+    baseline.warnings = Some(warnings);
+    Ok(())
+}
+
+/// Tests the ends of the range `runs` times each for `--validate`, failing if
+/// the results of either vary. Without a `--start` nightly, the start of a
+/// nightly bisection isn't known yet, so only the end is tested.
+fn validate_test(cfg: &Config, client: &Client, runs: usize) -> Result<(), Error> {
+    let toolchain = |spec| Toolchain {
+        spec,
+        host: cfg.args.host.clone(),
+        name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+        std_targets: cfg.std_targets(),
+    };
+    let (ends, dl_spec) = if cfg.is_commit {
+        let (start, end) = ci_bounds(cfg);
+        let ends = [start, end]
+            .iter()
+            .map(|sha| {
+                let commit = git::expand_commit(sha, &cfg.args.branch)?;
+                Ok(toolchain(cfg.ci_spec(commit)))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        (ends, DownloadParams::for_ci(cfg))
+    } else {
+        let mut ends = Vec::new();
+        if let Some(Bound::Date(date)) = cfg.args.start {
+            ends.push(toolchain(cfg.dated_spec(date)));
+        }
+        ends.push(toolchain(cfg.dated_spec(get_end_date(cfg, client)?)));
+        (ends, DownloadParams::for_nightly(cfg))
+    };
+
+    for t in &ends {
+        eprintln!("testing {} {} times to check that the test is deterministic", t, runs);
+        t.install(client, &dl_spec)?;
+        let outcomes = (0..runs).map(|_| t.test(cfg)).collect::<Vec<_>>();
+        if !cfg.args.preserve {
+            let _ = t.remove(&dl_spec);
+        }
+        if outcomes.iter().any(|&outcome| outcome != outcomes[0]) {
+            bail!(
+                "testing {} {} times gave different results: {:?}. The test is flaky, so \
+                 a bisection would not be meaningful; make it deterministic first",
+                t,
+                runs,
+                outcomes
+            );
+        }
+        eprintln!("{} consistently tested as {:?}", t, outcomes[0]);
+    }
+    Ok(())
+}
+
+/// Scans the whole range for changes in outcome in either direction and
+/// reports each of them.
+fn find_all_transitions(cfg: &Config, client: &Client) -> Result<(), Error> {
+    let (toolchains, dl_spec) = if cfg.is_commit {
+        let (start, end) = ci_bounds(cfg);
+        (ci_toolchains_between(cfg, &start, &end)?, DownloadParams::for_ci(cfg))
+    } else {
+        let start = match cfg.args.start {
+            Some(Bound::Date(date)) => date,
+            _ => bail!("--all-transitions needs a --start date to bisect nightlies"),
         };
+        let end = get_end_date(cfg, client)?;
+        let toolchains = toolchains_between(
+            cfg,
+            cfg.dated_spec(start),
+            cfg.dated_spec(end),
+        );
+        (toolchains, DownloadParams::for_nightly(cfg))
+    };
 
-        // We will download and extract the tarballs into this directory before installing.
-        // Using `~/.rustup/tmp` instead of $TMPDIR ensures we could always perform installation by
-        // renaming instead of copying the whole directory.
-        let rustup_tmp_path = toolchains_path.join("tmp");
-        if !rustup_tmp_path.exists() {
-            fs::create_dir(&rustup_tmp_path)?;
-        }
+    eprintln!(
+        "scanning {} toolchains for all transitions, assuming no change within \
+         {} toolchains whose ends agree",
+        toolchains.len(),
+        cfg.args.scan_granularity
+    );
+    let transitions = all_transitions(&toolchains, cfg.args.scan_granularity, |t| {
+        install_and_test(t, cfg, client, &dl_spec)
+    });
+    cfg.check_fatal()?;
 
-        toolchains_path.push("toolchains");
-        if !toolchains_path.is_dir() {
-            bail!(
-                "`{}` is not a directory. Please install rustup.",
-                toolchains_path.display()
+    let describe = |s: Satisfies| match s {
+        Satisfies::Yes => "regressed",
+        Satisfies::No => "baseline",
+        Satisfies::Unknown => "unknown",
+    };
+    if transitions.is_empty() {
+        eprintln!("no transitions found in the searched range");
+    } else {
+        eprintln!("found {} transitions:", transitions.len());
+    }
+    for transition in &transitions {
+        eprintln!(
+            "  {} -> {} between {} and {}",
+            describe(transition.from),
+            describe(transition.to),
+            toolchains[transition.before],
+            toolchains[transition.after],
+        );
+        if transition.after - transition.before > 1 {
+            eprintln!(
+                "    ({} toolchains in between could not be tested)",
+                transition.after - transition.before - 1
             );
         }
+    }
 
-        let is_commit = match (args.start.clone(), args.end.clone()) {
-            (Some(Bound::Commit(_)), Some(Bound::Commit(_)))
-            | (None, Some(Bound::Commit(_)))
-            | (Some(Bound::Commit(_)), None) => Some(true),
+    cfg.stats.print_requested(&cfg.args);
 
-            (Some(Bound::Date(_)), Some(Bound::Date(_)))
-            | (None, Some(Bound::Date(_)))
-            | (Some(Bound::Date(_)), None) => Some(false),
+    Ok(())
+}
 
-            (None, None) => None,
+/// Bisects the commits of a local rust checkout, building each one probed
+/// instead of downloading it.
+fn bisect_local(cfg: &Config, checkout: &Path) -> Result<(), Error> {
+    let (start, end) = match (&cfg.args.start, &cfg.args.end) {
+        (&Some(Bound::Commit(ref start)), &Some(Bound::Commit(ref end))) => (start, end),
+        _ => bail!("--local-build needs commits as --start and --end"),
+    };
 
-            (start, end) => bail!(
-                "cannot take different types of bounds for start/end, got start: {:?} and end {:?}",
-                start,
-                end
-            ),
-        };
+    let commits = git::get_local_commits_between(checkout, start, end)?;
+    let toolchains = commits
+        .into_iter()
+        .map(|commit| {
+            let t = Toolchain {
+                spec: ToolchainSpec::Local { commit: commit.sha },
+                host: cfg.args.host.clone(),
+                name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+                std_targets: cfg.std_targets(),
+            };
+            t
+        })
+        .collect::<Vec<_>>();
 
-        if is_commit == Some(false) && args.by_commit {
-            eprintln!("finding commit range that corresponds to dates specified");
-            match (args.start, args.end) {
-                (Some(b1), Some(b2)) => {
-                    args.start = Some(b1.as_commit()?);
-                    args.end = Some(b2.as_commit()?);
+    let dl_spec = DownloadParams::for_ci(cfg);
+    eprintln!("building and testing commits");
+    let found = search(cfg, &toolchains, |t| {
+        let started = Instant::now();
+        let r = match t.build_locally(cfg, checkout, &dl_spec) {
+            Ok(()) => {
+                eprintln!("testing {}", t);
+                let r = t.test_retrying(cfg).satisfies();
+                if !cfg.args.preserve {
+                    let _ = t.remove(&dl_spec);
                 }
-                _ => unreachable!(),
+                eprintln!("tested {}, got {}", t, r);
+                r
             }
-        }
-
-        Ok(Config {
-            is_commit: args.by_commit || is_commit == Some(true),
-            args,
-            target,
-            toolchains_path,
-            rustup_tmp_path,
-        })
-    }
-}
+            Err(err) => {
+                eprintln!("failed to build {}: {}", t, err);
+                cfg.stats.record_failed_install();
+                Satisfies::Unknown
+            }
+        };
+        cfg.stats.record_probe(t, r, started.elapsed());
+        r
+    })?;
 
-fn check_bounds(start: &Option<Bound>, end: &Option<Bound>) -> Result<(), Error> {
-    match (&start, &end) {
-        (Some(Bound::Date(start)), Some(Bound::Date(end))) if end < start => {
-            bail!(
-                "end should be after start, got start: {:?} and end {:?}",
-		start,
-		end
-            );
-	},
-        _ => {}
+    eprintln!(
+        "searched commits {} through {}",
+        toolchains.first().unwrap(),
+        toolchains.last().unwrap(),
+    );
+    let direction = cfg.args.bisect_direction;
+    match cfg.stats.result_of(&toolchains[found]) {
+        Some(Satisfies::Yes) => eprintln!("{}: {}", direction.found(), toolchains[found]),
+        _ => eprintln!("error: {}. Expanding the bounds may help.", direction.not_found()),
     }
 
+    cfg.stats.print_requested(&cfg.args);
     Ok(())
 }
 
-fn run() -> Result<(), Error> {
-    env_logger::try_init()?;
-    let args = env::args_os().filter(|a| a != "bisect-rustc");
-    let args = Opts::from_iter(args);
-    check_bounds(&args.start, &args.end)?;
-    let cfg = Config::from_args(args)?;
+/// Tests the regressed toolchain and the one before it again with verbose
+/// output, saving it to `--rerun-verbose`, so that the transition is recorded
+/// in detail without every probe being verbose.
+fn rerun_decisive(cfg: &Config, client: &Client, bisection_result: &BisectionResult) {
+    let dir = match cfg.args.rerun_verbose {
+        Some(ref dir) => dir,
+        None => return,
+    };
+    let found = bisection_result.found;
+    let first = if found == 0 { 0 } else { found - 1 };
+    let dl_spec = &bisection_result.dl_spec;
+
+    for t in &bisection_result.searched[first..found + 1] {
+        eprintln!("testing {} again verbosely", t);
+        if let Err(err) = t.install(client, dl_spec) {
+            eprintln!("failed to install {}: {}", t, err);
+            continue;
+        }
+        for target in cfg.test_targets() {
+            match t.run_test(cfg, true, target) {
+                Ok((output, _)) => t.save_log(dir, target, &output_log(&output)),
+                Err(err) => eprintln!("could not test {}: {}", t, err),
+            }
+        }
+        if !cfg.args.preserve {
+            let _ = t.remove(dl_spec);
+        }
+    }
+}
 
-    let client = Client::new();
+/// For `--verify-regression`: reinstalls the regressed toolchain and the one
+/// before it and tests them again, without any cached results and in a fresh
+/// copy of the test directory, to check that the boundary holds up outside
+/// of whatever state the bisection left behind.
+fn verify_regression(cfg: &Config, client: &Client, bisection_result: &BisectionResult) {
+    if !cfg.args.verify_regression || bisection_result.found == 0 {
+        return;
+    }
+    // the toolchains right before the regressing one may have come out
+    // unknown, so the boundary starts at the last one seen without it
+    let regressed = &bisection_result.searched[bisection_result.found];
+    let before = bisection_result.searched[..bisection_result.found]
+        .iter()
+        .rev()
+        .find(|t| cfg.stats.result_of(t) == Some(Satisfies::No));
+    let before = match before {
+        Some(before) => before,
+        None => {
+            eprintln!(
+                "warning: no toolchain before {} was tested without the regression; \
+                 not verifying the boundary",
+                regressed
+            );
+            return;
+        }
+    };
+    let dl_spec = DownloadParams {
+        force_install: true,
+        ..bisection_result.dl_spec.clone()
+    };
 
-    if let Some(ref bound) = cfg.args.install {
-        install(&cfg, &client, bound)
-    } else {
-        bisect(&cfg, &client)
+    cfg.verifying.set(true);
+    let mut results = Vec::new();
+    for t in &[before, regressed] {
+        eprintln!("verifying {} in a fresh environment", t);
+        let r = match t.install(client, &dl_spec) {
+            Ok(()) => t.test(cfg).satisfies(),
+            Err(err) => {
+                eprintln!("failed to install {}: {}", t, err);
+                Satisfies::Unknown
+            }
+        };
+        if !cfg.args.preserve {
+            let _ = t.remove(&dl_spec);
+        }
+        eprintln!("verified {}, got {}", t, r);
+        results.push(r);
+    }
+    cfg.verifying.set(false);
+
+    match (results[0], results[1]) {
+        (Satisfies::No, Satisfies::Yes) => eprintln!(
+            "verified: the boundary between {} and {} holds up in a fresh environment",
+            before, regressed
+        ),
+        (r_before, r_regressed) => eprintln!(
+            "warning: the boundary did not hold up in a fresh environment: expected {} \
+             to give No and {} to give Yes, got {} and {}; the test may be flaky or \
+             depend on state left behind by earlier runs",
+            before, regressed, r_before, r_regressed
+        ),
     }
 }
 
-fn install(cfg: &Config, client: &Client, bound: &Bound) -> Result<(), Error> {
-    match *bound {
-        Bound::Commit(ref sha) => {
-            let sha = git::expand_commit(sha)?;
-            let mut t = Toolchain {
-                spec: ToolchainSpec::Ci {
-                    commit: sha.clone(),
-                    alt: cfg.args.alt,
-                },
-                host: cfg.args.host.clone(),
-                std_targets: vec![cfg.args.host.clone(), cfg.target.clone()],
+/// Saves the tarballs of the regressing toolchain to `--copy-artifacts-to`,
+/// unextracted, so that it can be reinstalled later.
+fn copy_artifacts(cfg: &Config, client: &Client, bisection_result: &BisectionResult) -> Result<(), Error> {
+    let dir = match cfg.args.copy_artifacts_to {
+        Some(ref dir) => dir,
+        None => return Ok(()),
+    };
+    let t = &bisection_result.searched[bisection_result.found];
+    let dest = dir.join(t.rustup_name());
+    fs::create_dir_all(&dest)?;
+
+    eprintln!("saving the tarballs of {} to {}", t, dest.display());
+    for component in t.components(&bisection_result.dl_spec, &dest) {
+        let mut saved = false;
+        for ext in &["xz", "gz"] {
+            let url = format!("{}.{}", component.url, ext);
+            let (response, mut bar) = match download_progress(client, &component.name, &url, bisection_result.dl_spec.retries) {
+                Ok(download) => download,
+                Err(DownloadError::NotFound(_)) => continue,
+                Err(err) => return Err(err.into()),
             };
-            t.std_targets.sort();
-            t.std_targets.dedup();
-            let dl_params = DownloadParams::for_ci(cfg);
-            t.install(client, &dl_params)?;
+            let filename = url.rsplit('/').next().unwrap();
+            let mut file = fs::File::create(dest.join(filename))?;
+            io::copy(&mut TeeReader::new(response, &mut bar), &mut file)?;
+            saved = true;
+            break;
         }
-        Bound::Date(date) => {
-            let mut t = Toolchain {
-                spec: ToolchainSpec::Nightly { date: date },
-                host: cfg.args.host.clone(),
-                std_targets: vec![cfg.args.host.clone(), cfg.target.clone()],
-            };
-            t.std_targets.sort();
-            t.std_targets.dedup();
-            let dl_params = DownloadParams::for_nightly(cfg);
-            t.install(client, &dl_params)?;
+        if !saved {
+            bail!("could not find the tarball of {} for {}", component.name, t);
         }
     }
-
     Ok(())
 }
 
-fn bisect(cfg: &Config, client: &Client) -> Result<(), Error> {
-    if cfg.is_commit {
-        let bisection_result = bisect_ci(&cfg, &client)?;
-        print_results(cfg, client, &bisection_result);
-    } else {
-        let nightly_bisection_result = bisect_nightlies(&cfg, &client)?;
-        print_results(cfg, client, &nightly_bisection_result);
-        let nightly_regression = &nightly_bisection_result.searched[nightly_bisection_result.found];
+/// Explains why a result is ambiguous, if it is: either the regression wasn't
+/// confirmed, or the toolchain just before the one found wasn't itself
+/// confirmed to be good, e.g. because it could not be installed.
+fn ambiguity(cfg: &Config, bisection_result: &BisectionResult, found: bool) -> Option<String> {
+    let BisectionResult {
+        searched: toolchains,
+        found: found_idx,
+        unrefined,
+        ..
+    } = bisection_result;
+    if !found {
+        return Some("the regression was not confirmed".to_string());
+    }
+    if *found_idx == 0 {
+        return Some(format!("{} is the start of the searched range", toolchains[0]));
+    }
+    if *unrefined {
+        return Some(format!(
+            "only one in every {} commits was tested, so the regression is {}",
+            cfg.args.min_commit_interval,
+            unrefined_range(toolchains, *found_idx)
+        ));
+    }
 
-        if let ToolchainSpec::Nightly { date } = nightly_regression.spec {
-            let previous_date = date - chrono::Duration::days(1);
+    let previous = &toolchains[*found_idx - 1];
+    match cfg.stats.result_of(previous) {
+        Some(Satisfies::No) => None,
+        result => Some(format!(
+            "the toolchain before {} ({}) was {}, so the regression may lie \
+             anywhere between the last known good toolchain and {}",
+            toolchains[*found_idx],
+            previous,
+            match result {
+                Some(Satisfies::Unknown) => "unknown",
+                _ => "never tested",
+            },
+            toolchains[*found_idx],
+        )),
+    }
+}
 
-            if let Bound::Commit(bad_commit) = Bound::Date(date).as_commit()? {
-                if let Bound::Commit(working_commit) = Bound::Date(previous_date).as_commit()? {
-                    eprintln!(
-                        "looking for regression commit between {} and {}",
-                        date.format("%Y-%m-%d"),
-                        previous_date.format("%Y-%m-%d"),
-                    );
+/// A rough indication of how much to trust a result, for whoever reads the
+/// report. It is high when the boundary was confirmed on both sides and every
+/// toolchain tested gave an answer, medium when the boundary was confirmed but
+/// some toolchains along the way were unknown, and low otherwise.
+enum Confidence {
+    High,
+    Medium { unknowns: usize },
+    Low,
+}
 
-                    let ci_bisection_result = bisect_ci_between(cfg, client, &working_commit, &bad_commit)?;
-                    print_results(cfg, client, &ci_bisection_result);
-                    print_final_report(&nightly_bisection_result, &ci_bisection_result);
-                }
-            }
+impl Confidence {
+    fn of(cfg: &Config, ambiguous: bool) -> Confidence {
+        match cfg.stats.unknowns.get() {
+            _ if ambiguous => Confidence::Low,
+            0 => Confidence::High,
+            unknowns => Confidence::Medium { unknowns },
         }
     }
+}
 
-    Ok(())
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Confidence::High => write!(f, "high (the boundary was confirmed on both sides)"),
+            Confidence::Medium { unknowns } => write!(
+                f,
+                "medium ({} toolchain(s) could not be tested along the way)",
+                unknowns
+            ),
+            Confidence::Low => write!(f, "low (the boundary could not be confirmed)"),
+        }
+    }
+}
+
+/// Installs and tests a single toolchain, uninstalling it afterwards unless
+/// `--preserve` was passed. Failing to install it yields `Satisfies::Unknown`.
+fn install_and_test(
+    t: &Toolchain,
+    cfg: &Config,
+    client: &Client,
+    dl_spec: &DownloadParams,
+) -> Satisfies {
+    if cfg.fatal.borrow().is_some() {
+        // the search winds down without testing anything more
+        return Satisfies::Unknown;
+    }
+    let started = Instant::now();
+    if let Some(outcome) = cfg.resumed_outcome(t).or_else(|| t.cached_test(cfg)) {
+        let r = outcome.satisfies();
+        eprintln!("tested {}, got {}", t, r);
+        cfg.stats.record_cache_hit();
+        cfg.stats.record_probe(t, r, started.elapsed());
+        cfg.save_state();
+        return r;
+    }
+    eprintln!("installing {}", t);
+    let r = match t.install(client, dl_spec) {
+        Ok(()) => {
+            eprintln!("testing {}", t);
+            let r = t.test_retrying(cfg).satisfies();
+            if !cfg.args.preserve {
+                let _ = t.remove(dl_spec);
+            }
+            eprintln!("tested {}, got {}", t, r);
+            r
+        }
+        Err(err @ InstallError::LowDiskSpace { .. }) => {
+            // testing on would only turn every remaining toolchain unknown
+            *cfg.fatal.borrow_mut() = Some(err.into());
+            return Satisfies::Unknown;
+        }
+        Err(err) => {
+            let _ = t.remove(dl_spec);
+            eprintln!("failed to install {}: {:?}", t, err);
+            cfg.stats.record_failed_install();
+            if let Some(ref dir) = cfg.args.keep_failed_logs {
+                t.save_log(dir, None, &format!("failed to install: {}", err));
+            }
+            if let Some(ref dir) = cfg.args.log_dir {
+                t.save_log(dir, None, &format!("failed to install: {}", err));
+            }
+            Satisfies::Unknown
+        }
+    };
+    cfg.stats.record_probe(t, r, started.elapsed());
+    cfg.save_state();
+    r
 }
 
-fn print_results(cfg: &Config, client: &Client, bisection_result: &BisectionResult) {
+/// Prints the result of a bisection, returning whether a regression was found.
+fn print_results(cfg: &Config, client: &Client, bisection_result: &BisectionResult) -> bool {
     let BisectionResult {
         searched: toolchains,
         dl_spec,
         found,
+        unrefined,
     } = bisection_result;
 
     eprintln!(
@@ -857,38 +5938,276 @@ fn print_results(cfg: &Config, client: &Client, bisection_result: &BisectionResu
 
     if toolchains[*found] == *toolchains.last().unwrap() {
         let t = &toolchains[*found];
-        let r = match t.install(&client, &dl_spec) {
-            Ok(()) => {
-                let outcome = t.test(&cfg);
-                if !cfg.args.preserve {
-                    let _ = t.remove(&dl_spec);
-                }
-                // we want to fail, so a successful build doesn't satisfy us
-                match outcome {
-                    TestOutcome::Baseline => Satisfies::No,
-                    TestOutcome::Regressed => Satisfies::Yes,
-                }
-            }
-            Err(_) => {
-                let _ = t.remove(&dl_spec);
-                Satisfies::Unknown
-            }
-        };
-        match r {
+        match install_and_test(t, cfg, client, dl_spec) {
             Satisfies::Yes => {}
             Satisfies::No | Satisfies::Unknown => {
-                eprintln!("error: The regression was not found. Expanding the bounds may help.");
-                return;
+                eprintln!(
+                    "error: {}. Expanding the bounds may help.",
+                    cfg.args.bisect_direction.not_found()
+                );
+                return false;
+            }
+        }
+    }
+
+    if *unrefined {
+        eprintln!(
+            "{}: {}",
+            cfg.args.bisect_direction.found(),
+            unrefined_range(toolchains, *found)
+        );
+    } else {
+        eprintln!("{}: {}", cfg.args.bisect_direction.found(), toolchains[*found]);
+    }
+    true
+}
+
+/// Where the regression is after a coarse bisection whose gap wasn't
+/// searched: somewhere after the last commit tested without it.
+fn unrefined_range(toolchains: &[Toolchain], found: usize) -> String {
+    format!(
+        "one of the commits after {} up to {}",
+        toolchains[found - 1],
+        toolchains[found]
+    )
+}
+
+#[test]
+fn test_unrefined_range() {
+    // what `bisect_ci_coarsely` reports when the gap isn't searched: the
+    // coarse commits around it, not the commit that ends it
+    let coarse = ["aaa", "ddd", "ggg"]
+        .iter()
+        .map(|commit| Toolchain {
+            spec: ToolchainSpec::Ci {
+                commit: commit.to_string(),
+                alt: false,
+                channel: ReleaseChannel::Nightly,
+            },
+            host: "x86_64-unknown-linux-gnu".to_string(),
+            std_targets: vec![],
+            name_prefix: String::new(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(unrefined_range(&coarse, 2), "one of the commits after ddd up to ggg");
+    assert_eq!(unrefined_range(&coarse, 1), "one of the commits after aaa up to ddd");
+}
+
+/// Asks the user what to make of a test run with `--prompt`; `None` means
+/// retry. Without an answer, because stdin was closed or `--prompt-timeout`
+/// passed, it is the `--prompt-default`.
+fn prompt_outcome(cfg: &Config) -> Option<TestOutcome> {
+    let ask = || {
+        Select::new()
+            .items(&["mark regressed", "mark baseline", "retry"])
+            .default(0)
+            .interact()
+    };
+    let answer = match cfg.args.prompt_timeout {
+        Some(secs) => {
+            // the prompt itself can't be interrupted, so it is only shown
+            // once the user is there to answer it
+            eprintln!("press Enter within {}s to answer", secs);
+            if !stdin_ready(secs) {
+                None
+            } else {
+                let mut line = String::new();
+                match io::stdin().read_line(&mut line) {
+                    Ok(0) | Err(_) => None,
+                    Ok(_) => ask().ok(),
+                }
             }
         }
+        None => ask().ok(),
+    };
+    match answer {
+        Some(0) => Some(TestOutcome::Regressed),
+        Some(1) => Some(TestOutcome::Baseline),
+        Some(2) => None,
+        Some(_) => unreachable!(),
+        None => {
+            eprintln!("no answer, taking the --prompt-default: {:?}", cfg.args.prompt_default);
+            Some(cfg.args.prompt_default)
+        }
+    }
+}
+
+/// Waits up to `secs` seconds for input on stdin without reading it, so that
+/// nothing is left reading the terminal once the time is up.
+#[cfg(unix)]
+fn stdin_ready(secs: u64) -> bool {
+    let mut fds = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = cmp::min(secs.saturating_mul(1000), i32::max_value() as u64) as libc::c_int;
+    unsafe { libc::poll(&mut fds, 1, millis) > 0 }
+}
+
+/// Elsewhere stdin can't be waited on with a timeout, so this just waits.
+#[cfg(not(unix))]
+fn stdin_ready(_secs: u64) -> bool {
+    true
+}
+
+/// The outcome of a toolchain tested for several targets: regressed if it
+/// regressed for any of them, otherwise unknown if any was unknown.
+fn combined_outcome(outcomes: &[TestOutcome]) -> TestOutcome {
+    if outcomes.contains(&TestOutcome::Regressed) {
+        TestOutcome::Regressed
+    } else if outcomes.contains(&TestOutcome::Unknown) {
+        TestOutcome::Unknown
+    } else {
+        TestOutcome::Baseline
+    }
+}
+
+#[test]
+fn test_combined_outcome() {
+    use TestOutcome::*;
+    assert_eq!(combined_outcome(&[Baseline, Unknown, Regressed]), Regressed);
+    assert_eq!(combined_outcome(&[Baseline, Unknown]), Unknown);
+    assert_eq!(combined_outcome(&[Baseline, Baseline]), Baseline);
+}
+
+/// Writes how the regression in `regressed` was found to `--write-repro`, as
+/// `key: value` lines.
+fn write_repro(cfg: &Config, regressed: &Toolchain) -> Result<(), Error> {
+    let path = match cfg.args.write_repro {
+        Some(ref path) => path,
+        None => return Ok(()),
+    };
+    // lossy rather than `env::args`, which would panic after the bisection
+    let args = env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "bisect-rustc")
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>();
+    let mut repro = format!(
+        "command: cargo-bisect-rustc {}\nhost: {}\nregressed: {}\n",
+        args.join(" "),
+        cfg.args.host,
+        regressed
+    );
+    for link in pull_request_links(cfg, regressed) {
+        repro.push_str(&format!("pull request: {}\n", link));
+    }
+    fs::write(path, repro)
+        .map_err(|err| format_err!("failed to write {}: {}", path.display(), err))?;
+    eprintln!("wrote the reproduction to {}", path.display());
+    Ok(())
+}
+
+/// Quotes `arg` for a POSIX shell if it needs it.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[test]
+fn test_shell_quote() {
+    assert_eq!(shell_quote("--start=2019-01-01"), "--start=2019-01-01");
+    assert_eq!(shell_quote("contains:no type"), "'contains:no type'");
+    assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    assert_eq!(shell_quote(""), "''");
+}
+
+/// Links to the pull requests merged by the CI commit `t`: the one bors
+/// merged, and for a rollup, those rolled up into it.
+fn pull_request_links(cfg: &Config, t: &Toolchain) -> Vec<String> {
+    let commit = match t.spec {
+        ToolchainSpec::Ci { ref commit, .. } => commit,
+        _ => return Vec::new(),
+    };
+    match cfg.commit_messages.borrow().get(commit) {
+        Some(message) => pull_requests(message)
+            .into_iter()
+            .map(|pr| format!("https://github.com/rust-lang/rust/pull/{}", pr))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// The pull requests a bors merge commit message refers to: `Auto merge of
+/// #N` in its summary, and the ` - #N (...)` lines listing the pull requests
+/// of a rollup, up to the ones listed as failed merges, which it doesn't
+/// contain.
+fn pull_requests(message: &str) -> Vec<u32> {
+    let number = |s: &str| -> Option<u32> {
+        let digits = s.chars().take_while(|c| c.is_ascii_digit()).collect::<String>();
+        digits.parse().ok()
+    };
+    let mut lines = message.lines();
+    let mut prs = Vec::new();
+    if let Some(summary) = lines.next() {
+        if let Some(pos) = summary.find("merge of #") {
+            prs.extend(number(&summary[pos + "merge of #".len()..]));
+        }
     }
+    for line in lines.take_while(|line| !line.trim_start().starts_with("Failed merges")) {
+        if line.trim_start().starts_with("- #") {
+            prs.extend(number(&line.trim_start()["- #".len()..]));
+        }
+    }
+    let mut seen = Vec::new();
+    prs.retain(|pr| {
+        let new = !seen.contains(pr);
+        seen.push(*pr);
+        new
+    });
+    prs
+}
 
-    eprintln!("regression in {}", toolchains[*found]);
+#[test]
+fn test_pull_requests() {
+    assert_eq!(
+        pull_requests("Auto merge of #51361 - oli-obk:sanity_check_consts, r=nikomatsakis\n\nDo a basic sanity check"),
+        vec![51361]
+    );
+    let rollup = "Auto merge of #66000 - Centril:rollup-abc, r=Centril\n\
+                  \n\
+                  Rollup of 2 pull requests\n\
+                  \n\
+                  Successful merges:\n\
+                  \n - #65900 (Fix #65000)\n - #65950 (Improve docs)\n\
+                  \n\
+                  Failed merges:\n\
+                  \n - #65999 (Fix #65998)\n";
+    assert_eq!(pull_requests(rollup), vec![66000, 65900, 65950]);
+    assert!(pull_requests("Bump the version").is_empty());
+}
+
+/// Whether a bors merge commit message is that of a rollup, which merges
+/// several pull requests at once.
+fn is_rollup(message: &str) -> bool {
+    let summary = message.lines().next().unwrap_or("");
+    summary.starts_with("Rollup merge of ")
+        || summary.contains(":rollup-")
+        || message.lines().any(|line| line.trim().starts_with("Rollup of "))
+}
+
+#[test]
+fn test_is_rollup() {
+    assert!(is_rollup(
+        "Auto merge of #66000 - Centril:rollup-abc, r=Centril\n\nRollup of 2 pull requests"
+    ));
+    assert!(is_rollup("Auto merge of #66001 - a:b, r=c\n\nRollup of 5 pull requests\n"));
+    assert!(is_rollup("Rollup merge of #65900 - a:fix, r=b"));
+    assert!(!is_rollup(
+        "Auto merge of #51361 - oli-obk:sanity_check_consts, r=nikomatsakis\n\nDo a basic sanity check"
+    ));
 }
 
 fn print_final_report(
+    cfg: &Config,
     nightly_bisection_result: &BisectionResult,
     ci_bisection_result: &BisectionResult,
+    confidence: Confidence,
 ) {
     let BisectionResult {
         searched: nightly_toolchains,
@@ -899,6 +6218,7 @@ fn print_final_report(
     let BisectionResult {
         searched: ci_toolchains,
         found: ci_found,
+        unrefined: ci_unrefined,
         ..
     } = ci_bisection_result;
 
@@ -913,7 +6233,11 @@ fn print_final_report(
 
     eprintln!("");
 
-    eprintln!("# Regression found in the compiler");
+    let (title, nightly_label, commit_label) = match cfg.args.bisect_direction {
+        BisectDirection::Regression => ("Regression", "regressed nightly", "regressed commit"),
+        BisectDirection::Fix => ("Fix", "fixed in nightly", "fixing commit"),
+    };
+    eprintln!("# {} found in the compiler", title);
     eprintln!("");
 
     eprintln!(
@@ -923,7 +6247,8 @@ fn print_final_report(
     );
 
     eprintln!(
-        "regressed nightly: {}",
+        "{}: {}",
+        nightly_label,
         nightly_toolchains[*nightly_found],
     );
 
@@ -933,10 +6258,30 @@ fn print_final_report(
         ci_toolchains.last().unwrap(),
     );
 
-    eprintln!(
-        "regressed commit: https://github.com/rust-lang/rust/commit/{}",
-        ci_toolchains[*ci_found],
-    );
+    if *ci_unrefined {
+        eprintln!(
+            "{}: {} (only one in every {} commits was tested)",
+            commit_label,
+            unrefined_range(ci_toolchains, *ci_found),
+            cfg.args.min_commit_interval
+        );
+    } else {
+        eprintln!(
+            "{}: https://github.com/rust-lang/rust/commit/{} ({})",
+            commit_label,
+            ci_toolchains[*ci_found],
+            match cfg.args.bisect_direction {
+                BisectDirection::Regression => "the first commit that regresses",
+                BisectDirection::Fix => "the first commit that fixes it",
+            }
+        );
+
+        for link in pull_request_links(cfg, &ci_toolchains[*ci_found]) {
+            eprintln!("pull request: {}", link);
+        }
+    }
+
+    eprintln!("bisection confidence: {}", confidence);
 
     eprintln!("source code: URL OF A REPOSITORY THAT REPRODUCES THE ERROR");
 
@@ -958,9 +6303,12 @@ fn print_final_report(
     eprintln!("</p></details>");
 }
 
+#[derive(Clone)]
 struct NightlyFinderIter {
     start_date: Date<Utc>,
     current_date: Date<Utc>,
+    /// The dates nightlies were published on, oldest first, if known.
+    published: Vec<Date<Utc>>,
 }
 
 impl NightlyFinderIter {
@@ -968,8 +6316,16 @@ impl NightlyFinderIter {
         Self {
             start_date,
             current_date: start_date,
+            published: Vec::new(),
         }
     }
+
+    /// Only yields dates on which a nightly was published, going back from
+    /// each date it would otherwise yield to the previous publication.
+    fn with_published(mut self, published: &[Date<Utc>]) -> Self {
+        self.published = published.to_vec();
+        self
+    }
 }
 
 impl Iterator for NightlyFinderIter {
@@ -991,10 +6347,93 @@ impl Iterator for NightlyFinderIter {
             };
 
         self.current_date = self.current_date - chrono::Duration::days(jump_length);
+        if !self.published.is_empty() {
+            let next_day = self.current_date + chrono::Duration::days(1);
+            self.current_date = published_before(&self.published, next_day);
+        }
         Some(self.current_date)
     }
 }
 
+/// The latest of the `published` dates before `date`, or just the day before
+/// if there is no such date.
+fn published_before(published: &[Date<Utc>], date: Date<Utc>) -> Date<Utc> {
+    published
+        .iter()
+        .rev()
+        .find(|&&published| published < date)
+        .cloned()
+        .unwrap_or_else(|| date - chrono::Duration::days(1))
+}
+
+/// The dates of the builds of `channel` listed in the index of all manifests
+/// kept next to the `dist` directory on the server, oldest first. Without
+/// it, missing builds are found by trying to download them.
+fn published_dates(
+    client: &Client,
+    server: &str,
+    channel: ReleaseChannel,
+    cache_dir: Option<&Path>,
+) -> Vec<Date<Utc>> {
+    let url = format!("{}/../manifests.txt", server);
+    eprintln!("fetching {}", url);
+    match fetch_manifest(client, &url, 0, cache_dir) {
+        Ok(text) => {
+            let dates = parse_manifest_index(&text, channel);
+            if dates.is_empty() {
+                eprintln!("warning: {} lists no {} builds", url, channel);
+            }
+            dates
+        }
+        Err(err) => {
+            eprintln!(
+                "warning: could not fetch the list of published {} builds ({}), \
+                 so missing ones are skipped one day at a time",
+                channel, err
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Parses the index of manifests, lines like
+/// `static.rust-lang.org/dist/2019-01-01/channel-rust-nightly.toml`.
+fn parse_manifest_index(text: &str, channel: ReleaseChannel) -> Vec<Date<Utc>> {
+    let suffix = format!("/channel-rust-{}.toml", channel);
+    let mut dates = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| line.ends_with(&suffix))
+        .filter_map(|line| line[..line.len() - suffix.len()].rsplit('/').next())
+        .filter_map(|date| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+        .map(|date| Date::from_utc(date, Utc))
+        .collect::<Vec<_>>();
+    dates.sort();
+    dates.dedup();
+    dates
+}
+
+#[test]
+fn test_published_dates() {
+    let index = "static.rust-lang.org/dist/2018-12-22/channel-rust-nightly.toml\n\
+                 static.rust-lang.org/dist/2018-12-22/channel-rust-beta.toml\n\
+                 static.rust-lang.org/dist/2018-12-30/channel-rust-nightly.toml\n\
+                 static.rust-lang.org/dist/2019-01-01/channel-rust-nightly.toml\n\
+                 static.rust-lang.org/dist/channel-rust-nightly.toml\n";
+    let day = |d| Date::from_utc(chrono::naive::NaiveDate::from_ymd(2018, 12, d), Utc);
+    let published = parse_manifest_index(index, ReleaseChannel::Nightly);
+    assert_eq!(
+        published,
+        vec![day(22), day(30), Date::from_utc(chrono::naive::NaiveDate::from_ymd(2019, 1, 1), Utc)]
+    );
+
+    // the holidays are skipped instead of tried day by day
+    assert_eq!(published_before(&published, day(30)), day(22));
+    let mut iter = NightlyFinderIter::new(published[2]).with_published(&published);
+    assert_eq!(iter.next(), Some(day(30)));
+    assert_eq!(iter.next(), Some(day(22)));
+}
+
 #[test]
 fn test_nightly_finder_iterator() {
     let start_date = chrono::Date::from_utc(
@@ -1018,6 +6457,144 @@ fn test_nightly_finder_iterator() {
     assert_eq!(start_date - chrono::Duration::days(78), iter.next().unwrap());
 }
 
+/// The nightly to end a nightly bisection at, reporting how it was chosen:
+/// `--end`, or else the installed default nightly, or else today's. Beta
+/// bisections end at the latest beta.
+fn get_end_date(cfg: &Config, client: &Client) -> Result<Date<Utc>, Error> {
+    if let Some(Bound::Date(date)) = cfg.args.end {
+        return Ok(date);
+    }
+
+    if cfg.args.no_default_nightly_fallback || cfg.args.channel != ReleaseChannel::Nightly {
+        let date = latest_nightly_date(client, &cfg.args.server, cfg.args.channel)?;
+        eprintln!(
+            "ending at {}, the latest {} available (no --end given)",
+            date.format("%Y-%m-%d"),
+            cfg.args.channel
+        );
+        return Ok(date);
+    }
+
+    if let Some(date) = Toolchain::default_nightly() {
+        eprintln!(
+            "ending at {}, the installed default nightly (no --end given; pass \
+             --no-default-nightly-fallback to use the latest nightly instead)",
+            date.format("%Y-%m-%d")
+        );
+        Ok(date)
+    } else {
+        let date = chrono::Utc::now().date();
+        eprintln!(
+            "ending at today's nightly, {} (no --end given and the default toolchain \
+             is not a nightly)",
+            date.format("%Y-%m-%d")
+        );
+        Ok(date)
+    }
+}
+
+/// Which requests a proxy is used for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ProxyScheme {
+    All,
+    Http,
+    Https,
+}
+
+/// The proxies to use, most specific first: `--proxy` for everything, or
+/// those given by the environment variables curl also honors.
+fn proxies<F>(flag: Option<&str>, var: F) -> Vec<(ProxyScheme, String)>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    if let Some(proxy) = flag {
+        return vec![(ProxyScheme::All, proxy.to_string())];
+    }
+    let lookup = |name: &str| {
+        var(name)
+            .or_else(|| var(&name.to_lowercase()))
+            .filter(|value| !value.is_empty())
+    };
+    let mut proxies = Vec::new();
+    for &(name, scheme) in &[
+        ("HTTPS_PROXY", ProxyScheme::Https),
+        ("HTTP_PROXY", ProxyScheme::Http),
+        ("ALL_PROXY", ProxyScheme::All),
+    ] {
+        if let Some(proxy) = lookup(name) {
+            proxies.push((scheme, proxy));
+        }
+    }
+    proxies
+}
+
+#[test]
+fn test_proxies() {
+    let env = |name: &str| match name {
+        "https_proxy" => Some("http://secure:3128".to_string()),
+        "ALL_PROXY" => Some("http://all:3128".to_string()),
+        "HTTP_PROXY" => Some(String::new()),
+        _ => None,
+    };
+    assert_eq!(
+        proxies(None, env),
+        vec![
+            (ProxyScheme::Https, "http://secure:3128".to_string()),
+            (ProxyScheme::All, "http://all:3128".to_string()),
+        ]
+    );
+    assert_eq!(
+        proxies(Some("http://flag:8080"), env),
+        vec![(ProxyScheme::All, "http://flag:8080".to_string())]
+    );
+    assert!(proxies(None, |_| None).is_empty());
+    assert!(http_client(Some("http://flag:8080")).is_ok());
+    assert!(http_client(Some("not a url")).is_err());
+}
+
+/// The client all downloads are made with, going through the proxies given
+/// with `--proxy` or in the environment.
+fn http_client(proxy: Option<&str>) -> Result<Client, Error> {
+    let mut builder = Client::builder();
+    for (scheme, url) in proxies(proxy, |name| env::var(name).ok()) {
+        debug!("using proxy {} for {:?}", url, scheme);
+        let proxy = match scheme {
+            ProxyScheme::All => reqwest::Proxy::all(url.as_str()),
+            ProxyScheme::Http => reqwest::Proxy::http(url.as_str()),
+            ProxyScheme::Https => reqwest::Proxy::https(url.as_str()),
+        };
+        builder = builder.proxy(proxy.map_err(|err| format_err!("invalid proxy `{}`: {}", url, err))?);
+    }
+    Ok(builder.build()?)
+}
+
+/// The date of the most recent build of `channel` published on the server.
+fn latest_nightly_date(client: &Client, server: &str, channel: ReleaseChannel) -> Result<Date<Utc>, Error> {
+    let url = format!("{}/channel-rust-{}-date.txt", server, channel);
+    eprintln!("fetching {}", url);
+    let text = fetch_manifest(client, &url, 0, None)?;
+    match chrono::NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d") {
+        Ok(date) => Ok(Date::from_utc(date, Utc)),
+        Err(_) => bail!("unexpected contents of {}: `{}`", url, text.trim()),
+    }
+}
+
+/// The toolchains of the nightlies listed in a `--nightlies-from-file`.
+fn listed_nightlies(cfg: &Config, path: &Path) -> Result<Vec<Toolchain>, Error> {
+    Ok(read_nightlies_file(path)?
+        .into_iter()
+        .map(|date| {
+            let t = Toolchain {
+                spec: cfg.dated_spec(date),
+                host: cfg.args.host.clone(),
+                name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+                std_targets: cfg.std_targets(),
+            };
+            t
+        })
+        .collect())
+}
+
 fn bisect_nightlies(cfg: &Config, client: &Client) -> Result<BisectionResult, Error> {
     if cfg.args.alt {
         bail!("cannot bisect nightlies with --alt: not supported");
@@ -1025,6 +6602,58 @@ fn bisect_nightlies(cfg: &Config, client: &Client) -> Result<BisectionResult, Er
 
     let dl_spec = DownloadParams::for_nightly(&cfg);
 
+    if let Some(ref path) = cfg.args.nightlies_from_file {
+        let toolchains = listed_nightlies(cfg, path)?;
+
+        confirm_download(cfg, client, &toolchains, &dl_spec)?;
+        eprintln!("bisecting the {} nightlies listed in {}", toolchains.len(), path.display());
+        // nightlies that were never published fail to install and are
+        // treated as unknown, but the search has to start with a known
+        // baseline and end with a known regression, so unknown ones at
+        // either end are skipped over first
+        let mut results = HashMap::new();
+        let mut test = |t: &Toolchain| {
+            *results
+                .entry(t.to_string())
+                .or_insert_with(|| install_and_test(t, cfg, client, &dl_spec))
+        };
+        let mut start = 0;
+        while start < toolchains.len() && test(&toolchains[start]) == Satisfies::Unknown {
+            cfg.check_fatal()?;
+            eprintln!("could not test {}, trying the next listed nightly", toolchains[start]);
+            start += 1;
+        }
+        if start == toolchains.len() {
+            bail!("none of the nightlies listed in {} could be tested", path.display());
+        }
+        if test(&toolchains[start]) == Satisfies::Yes {
+            bail!(
+                "{}, the first listed nightly that could be tested, already has the regression",
+                toolchains[start]
+            );
+        }
+        let mut end = toolchains.len() - 1;
+        while end > start && test(&toolchains[end]) == Satisfies::Unknown {
+            cfg.check_fatal()?;
+            eprintln!("could not test {}, trying the previous listed nightly", toolchains[end]);
+            end -= 1;
+        }
+        if test(&toolchains[end]) != Satisfies::Yes {
+            bail!(
+                "{}, the last listed nightly that could be tested, doesn't have the regression",
+                toolchains[end]
+            );
+        }
+        let found = start + search(cfg, &toolchains[start..=end], &mut test)?;
+
+        return Ok(BisectionResult {
+            dl_spec,
+            searched: toolchains,
+            found,
+            unrefined: false,
+        });
+    }
+
     // before this date we didn't have -std packages
     let end_at = chrono::Date::from_utc(
         chrono::naive::NaiveDate::from_ymd(2015, 10, 20),
@@ -1032,60 +6661,91 @@ fn bisect_nightlies(cfg: &Config, client: &Client) -> Result<BisectionResult, Er
     );
     let mut first_success = None;
 
-    let mut last_failure = if let Some(Bound::Date(date)) = cfg.args.end {
-        date
-    } else {
-        if let Some(date) = Toolchain::default_nightly() {
-            date
-        } else {
-            chrono::Utc::now().date()
-        }
-    };
+    let mut last_failure = get_end_date(cfg, client)?;
 
-    let (mut nightly_date, has_start) = if let Some(Bound::Date(date)) = cfg.args.start {
+    let (mut nightly_date, mut has_start) = if let Some(Bound::Date(date)) = cfg.args.start {
         (date, true)
     } else {
         (last_failure, false)
     };
 
-    let mut nightly_iter = NightlyFinderIter::new(nightly_date);
+    let cache_dir = cfg.download_cache_dir();
+    let published = published_dates(
+        client,
+        &cfg.args.server,
+        cfg.args.channel,
+        cache_dir.as_ref().map(|dir| dir.as_path()),
+    );
+    let mut nightly_iter = NightlyFinderIter::new(nightly_date).with_published(&published);
+    let mut prefetcher = Prefetcher::new(cfg, client, &dl_spec);
 
     while nightly_date > end_at {
-        let mut t = Toolchain {
-            spec: ToolchainSpec::Nightly { date: nightly_date },
+        let t = Toolchain {
+            spec: cfg.dated_spec(nightly_date),
             host: cfg.args.host.clone(),
-            std_targets: vec![cfg.args.host.clone(), cfg.target.clone()],
+            name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+            std_targets: cfg.std_targets(),
         };
-        t.std_targets.sort();
-        t.std_targets.dedup();
-        if t.is_current_nightly() {
+        if !cfg.args.no_default_nightly_fallback && t.is_current_nightly() {
             eprintln!("checking {} from the currently installed default nightly \
                        toolchain as the last failure", t);
         } else {
             eprintln!("checking {}", t);
         }
-        match t.install(client, &dl_spec) {
+        let started = Instant::now();
+        if !has_start {
+            let upcoming = nightly_iter.clone().take(cfg.args.jobs.saturating_sub(1));
+            prefetcher.prefetch(upcoming.collect());
+        }
+        let cached = cfg.resumed_outcome(&t).or_else(|| t.cached_test(cfg));
+        let installed = if cached.is_some() {
+            cfg.stats.record_cache_hit();
+            Ok(())
+        } else {
+            match prefetcher.take(nightly_date) {
+                Some(result) => result,
+                None => t.install(client, &prefetcher.protecting(&dl_spec)),
+            }
+        };
+        match installed {
             Ok(()) => {
-                let outcome = t.test(&cfg);
+                let outcome = cached.unwrap_or_else(|| t.test_retrying(&cfg));
+                cfg.stats.record_probe(&t, outcome.satisfies(), started.elapsed());
+                cfg.save_state();
 
                 if !cfg.args.preserve {
                     let _ = t.remove(&dl_spec);
                 }
 
-                if let TestOutcome::Baseline = outcome {
-                    first_success = Some(nightly_date);
-                    break;
-                } else if has_start {
-                    return Err(format_err!("the --start nightly has the regression"))?;
-                } else {
-                    last_failure = nightly_date;
+                match outcome {
+                    TestOutcome::Baseline => {
+                        first_success = Some(nightly_date);
+                        break;
+                    }
+                    TestOutcome::Regressed if has_start && !cfg.args.keep_going => {
+                        return Err(format_err!("the --start nightly has the regression"))?;
+                    }
+                    TestOutcome::Regressed if has_start => {
+                        eprintln!("the --start nightly has the regression, looking further back");
+                        has_start = false;
+                        last_failure = nightly_date;
+                    }
+                    TestOutcome::Regressed => last_failure = nightly_date,
+                    TestOutcome::Unknown if has_start => {
+                        return Err(format_err!("could not test the --start nightly"))?;
+                    }
+                    TestOutcome::Unknown => {
+                        eprintln!("could not tell whether {} regressed, trying the one before", t);
+                        nightly_date = published_before(&published, nightly_date);
+                        continue;
+                    }
                 }
 
                 nightly_date = nightly_iter.next().unwrap();
             }
             Err(InstallError::NotFound { .. }) => {
-                // go back just one day, presumably missing nightly
-                nightly_date = nightly_date - chrono::Duration::days(1);
+                // presumably a missing nightly, so go back to the previous one
+                nightly_date = published_before(&published, nightly_date);
                 if !cfg.args.preserve {
                     let _ = t.remove(&dl_spec);
                 }
@@ -1102,59 +6762,178 @@ fn bisect_nightlies(cfg: &Config, client: &Client) -> Result<BisectionResult, Er
         }
     }
 
+    // the nightlies it fetched ahead are all older than the range left to search
+    drop(prefetcher);
+
     let first_success = first_success.ok_or(format_err!("could not find a nightly that built"))?;
 
-    let toolchains = toolchains_between(
+    let mut toolchains = toolchains_between(
         cfg,
-        ToolchainSpec::Nightly {
-            date: first_success,
-        },
-        ToolchainSpec::Nightly { date: last_failure },
+        cfg.dated_spec(first_success),
+        cfg.dated_spec(last_failure),
     );
+    if !published.is_empty() {
+        let last = toolchains.len() - 1;
+        let mut i = 0;
+        toolchains.retain(|t| {
+            let keep = i == 0 || i == last || published.contains(&t.spec.date().unwrap());
+            i += 1;
+            keep
+        });
+    }
 
-    let found = least_satisfying(&toolchains, |t| {
-        match t.install(&client, &dl_spec) {
-            Ok(()) => {
-                let outcome = t.test(&cfg);
-                // we want to fail, so a successful build doesn't satisfy us
-                let r = match outcome {
-                    TestOutcome::Baseline => Satisfies::No,
-                    TestOutcome::Regressed => Satisfies::Yes,
-                };
-                if !cfg.args.preserve {
-                    let _ = t.remove(&dl_spec);
-                }
-                eprintln!("tested {}, got {}", t, r);
-                r
-            }
-            Err(err) => {
-                let _ = t.remove(&dl_spec);
-                eprintln!("failed to install {}: {:?}", t, err);
-                Satisfies::Unknown
-            }
-        }
-    });
+    check_single_toolchain(cfg, client, &toolchains, &dl_spec)?;
+    confirm_download(cfg, client, &toolchains, &dl_spec)?;
+    let found = search(cfg, &toolchains, |t| install_and_test(t, cfg, client, &dl_spec))?;
 
     Ok(BisectionResult {
         dl_spec,
         searched: toolchains,
         found,
+        unrefined: false,
     })
 }
 
+/// Installs the nightlies the search for a good nightly will probably test
+/// next in the background, while the current one is tested, for `--jobs`.
+/// Those that end up unused are removed again unless `--preserve` is given.
+struct Prefetcher {
+    client: Client,
+    dl_spec: DownloadParams,
+    preserve: bool,
+    jobs: usize,
+    /// The toolchains to install only differ from this one in their date.
+    template: Toolchain,
+    pending: Vec<(Date<Utc>, thread::JoinHandle<Result<(), InstallError>>)>,
+}
+
+impl Prefetcher {
+    fn new(cfg: &Config, client: &Client, dl_spec: &DownloadParams) -> Prefetcher {
+        let template = Toolchain {
+            spec: cfg.dated_spec(chrono::Utc::now().date()),
+            host: cfg.args.host.clone(),
+            name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+            std_targets: cfg.std_targets(),
+        };
+        Prefetcher {
+            client: client.clone(),
+            // freeing space from the background could remove the toolchain
+            // being tested or another one being prefetched, so a prefetch
+            // short on space is left to the main thread
+            dl_spec: DownloadParams {
+                reclaim_space: false,
+                ..dl_spec.clone()
+            },
+            preserve: cfg.args.preserve,
+            jobs: cfg.args.jobs,
+            template,
+            pending: Vec::new(),
+        }
+    }
+
+    fn toolchain(&self, date: Date<Utc>) -> Toolchain {
+        Toolchain {
+            spec: ToolchainSpec::dated(self.template.spec.channel(), date),
+            ..self.template.clone()
+        }
+    }
+
+    /// Starts installing those of `dates` that aren't being installed yet.
+    fn prefetch(&mut self, dates: Vec<Date<Utc>>) {
+        for date in dates {
+            if self.pending.len() + 1 >= self.jobs || self.pending.iter().any(|&(d, _)| d == date) {
+                continue;
+            }
+            let t = self.toolchain(date);
+            let client = self.client.clone();
+            let dl_spec = self.dl_spec.clone();
+            eprintln!("downloading {} in the background", t);
+            self.pending
+                .push((date, thread::spawn(move || t.install(&client, &dl_spec))));
+        }
+    }
+
+    /// The result of installing the nightly of `date`, if it was prefetched.
+    /// A prefetch that ran out of disk space counts as not prefetched, for
+    /// the main thread to free some and install it.
+    fn take(&mut self, date: Date<Utc>) -> Option<Result<(), InstallError>> {
+        let idx = self.pending.iter().position(|&(d, _)| d == date)?;
+        let (_, handle) = self.pending.remove(idx);
+        // if the download thread died, installing again will tell why
+        match handle.join().ok()? {
+            Err(InstallError::LowDiskSpace { .. }) => None,
+            result => Some(result),
+        }
+    }
+
+    /// `dl_spec` for installing in the main thread, keeping the toolchains
+    /// being prefetched when freeing space.
+    fn protecting(&self, dl_spec: &DownloadParams) -> DownloadParams {
+        DownloadParams {
+            in_use: self
+                .pending
+                .iter()
+                .map(|&(date, _)| self.toolchain(date).rustup_name())
+                .collect(),
+            ..dl_spec.clone()
+        }
+    }
+}
+
+impl Drop for Prefetcher {
+    fn drop(&mut self) {
+        for (date, handle) in self.pending.drain(..).collect::<Vec<_>>() {
+            let _ = handle.join();
+            if !self.preserve {
+                let _ = self.toolchain(date).remove(&self.dl_spec);
+            }
+        }
+    }
+}
+
+/// Reads a list of nightly dates, one `YYYY-MM-DD` per line, in sorted order.
+/// Blank lines and lines starting with `#` are ignored.
+fn read_nightlies_file(path: &Path) -> Result<Vec<Date<Utc>>, Error> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format_err!("failed to read {}: {}", path.display(), err))?;
+
+    let mut dates = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let date = chrono::NaiveDate::parse_from_str(line, "%Y-%m-%d").map_err(|_| {
+            format_err!(
+                "{}:{}: expected a YYYY-MM-DD date, got `{}`",
+                path.display(),
+                i + 1,
+                line
+            )
+        })?;
+        dates.push(Date::from_utc(date, Utc));
+    }
+    dates.sort();
+    dates.dedup();
+
+    if dates.len() < 2 {
+        bail!("{} must list at least two nightly dates", path.display());
+    }
+    Ok(dates)
+}
+
 fn toolchains_between(cfg: &Config, a: ToolchainSpec, b: ToolchainSpec) -> Vec<Toolchain> {
-    match (a, b) {
-        (ToolchainSpec::Nightly { date: a }, ToolchainSpec::Nightly { date: b }) => {
+    match (a.date(), b.date()) {
+        (Some(a), Some(b)) => {
             let mut toolchains = Vec::new();
             let mut date = a;
             while date <= b {
-                let mut t = Toolchain {
-                    spec: ToolchainSpec::Nightly { date: date },
+                let t = Toolchain {
+                    spec: cfg.dated_spec(date),
                     host: cfg.args.host.clone(),
-                    std_targets: vec![cfg.args.host.clone(), cfg.target.clone()],
+                    name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+                    std_targets: cfg.std_targets(),
                 };
-                t.std_targets.sort();
-                t.std_targets.dedup();
                 toolchains.push(t);
                 date = date + Duration::days(1);
             }
@@ -1164,95 +6943,502 @@ fn toolchains_between(cfg: &Config, a: ToolchainSpec, b: ToolchainSpec) -> Vec<T
     }
 }
 
-fn bisect_ci(cfg: &Config, client: &Client) -> Result<BisectionResult, Error> {
-    eprintln!("bisecting ci builds");
+/// The start and end of a CI bisection, from `--start` and `--end` or their
+/// defaults.
+fn ci_bounds(cfg: &Config) -> (String, String) {
     let start = if let Some(Bound::Commit(ref sha)) = cfg.args.start {
-        sha
+        sha.clone()
     } else {
-        EPOCH_COMMIT
+        EPOCH_COMMIT.to_string()
     };
 
     let end = if let Some(Bound::Commit(ref sha)) = cfg.args.end {
-        sha
+        sha.clone()
     } else {
-        "origin/master"
+        cfg.default_end_ref()
     };
 
+    (start, end)
+}
+
+fn bisect_ci(cfg: &Config, client: &Client) -> Result<BisectionResult, Error> {
+    eprintln!("bisecting ci builds");
+    let (mut start, end) = ci_bounds(cfg);
+    if cfg.args.since_last_good {
+        eprintln!("looking for a commit without the regression before {}", end);
+        let before_end = git::commit_before(&end, 1, &cfg.args.branch)?;
+        start = ci_baseline_before(cfg, client, &before_end)?;
+    } else if cfg.args.keep_going && cfg.args.start.is_some() {
+        start = ci_baseline_before(cfg, client, &start)?;
+    }
+
     eprintln!("starting at {}, ending at {}", start, end);
 
-    bisect_ci_between(cfg, client, start, end)
+    bisect_ci_between(cfg, client, &start, &end)
+}
+
+/// For `--keep-going` and `--since-last-good`: `start` if it doesn't have the
+/// regression, otherwise the first commit without it found by going back
+/// ever further.
+fn ci_baseline_before(cfg: &Config, client: &Client, start: &str) -> Result<String, Error> {
+    let dl_spec = DownloadParams::for_ci(cfg);
+    let mut commit = git::expand_commit(start, &cfg.args.branch)?;
+    let mut distance = 4;
+    for _ in 0..10 {
+        let t = Toolchain {
+            spec: cfg.ci_spec(commit.clone()),
+            host: cfg.args.host.clone(),
+            name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+            std_targets: cfg.std_targets(),
+        };
+        let result = install_and_test(&t, cfg, client, &dl_spec);
+        cfg.check_fatal()?;
+        match result {
+            Satisfies::No => return Ok(commit),
+            Satisfies::Yes => {
+                eprintln!("{} has the regression, going back {} commits", t, distance);
+                commit = git::commit_before(&commit, distance, &cfg.args.branch)?;
+                distance *= 2;
+            }
+            Satisfies::Unknown => bail!(
+                "could not test {} while looking for a start without the regression; \
+                 its artifacts may have expired",
+                t
+            ),
+        }
+    }
+    bail!("found no commit without the regression before {}", start)
 }
 
 fn bisect_ci_between(cfg: &Config, client: &Client, start: &str, end: &str) -> Result<BisectionResult, Error> {
     let dl_spec = DownloadParams::for_ci(cfg);
-    let mut commits = get_commits(start, end)?;
-    let now = chrono::Utc::now();
-    commits.retain(|c| now.signed_duration_since(c.date).num_days() < 167);
+    let toolchains = ci_toolchains_between(cfg, start, end)?;
+    check_single_toolchain(cfg, client, &toolchains, &dl_spec)?;
 
-    if commits.is_empty() {
-        bail!(
-            "no commits between {} and {} within last 167 days",
-            start,
-            end
+    let interval = cfg.args.min_commit_interval;
+    if interval > 1 && toolchains.len() > 2 {
+        return bisect_ci_coarsely(cfg, client, toolchains, interval, dl_spec);
+    }
+
+    if !cfg.args.include_rollups {
+        let indices = non_rollup_indices(cfg, &toolchains);
+        if indices.len() < toolchains.len() {
+            return bisect_ci_skipping_rollups(cfg, client, toolchains, indices, dl_spec);
+        }
+    }
+
+    confirm_download(cfg, client, &toolchains, &dl_spec)?;
+    eprintln!("testing commits");
+    let found = search(cfg, &toolchains, |t| install_and_test(t, cfg, client, &dl_spec))?;
+
+    Ok(BisectionResult {
+        searched: toolchains,
+        found,
+        dl_spec,
+        unrefined: false,
+    })
+}
+
+/// Bisects only every `interval`th toolchain, then, if the user agrees,
+/// the toolchains in the gap the regression was found in.
+fn bisect_ci_coarsely(
+    cfg: &Config,
+    client: &Client,
+    toolchains: Vec<Toolchain>,
+    interval: usize,
+    dl_spec: DownloadParams,
+) -> Result<BisectionResult, Error> {
+    let indices = coarse_indices(toolchains.len(), interval);
+    let coarse = indices
+        .iter()
+        .map(|&i| toolchains[i].clone())
+        .collect::<Vec<_>>();
+    confirm_download(cfg, client, &coarse, &dl_spec)?;
+    eprintln!(
+        "testing one in every {} commits, {} of {}",
+        interval,
+        coarse.len(),
+        toolchains.len()
+    );
+    let found = search(cfg, &coarse, |t| install_and_test(t, cfg, client, &dl_spec))?;
+    if found == 0 || indices[found] - indices[found - 1] == 1 {
+        return Ok(BisectionResult {
+            searched: coarse,
+            found,
+            dl_spec,
+            unrefined: false,
+        });
+    }
+
+    let gap = toolchains[indices[found - 1]..=indices[found]].to_vec();
+    eprintln!(
+        "the regression is in one of the {} commits after {} up to {}",
+        gap.len() - 1,
+        gap[0],
+        gap[gap.len() - 1]
+    );
+    let refine = atty::is(atty::Stream::Stdin)
+        && atty::is(atty::Stream::Stderr)
+        && Confirmation::new()
+            .with_text("bisect the commits in this gap too?")
+            .default(true)
+            .interact()
+            .unwrap_or(false);
+    if !refine {
+        eprintln!(
+            "to narrow it down, run again with --start {} --end {}",
+            gap[0], gap[gap.len() - 1]
+        );
+        return Ok(BisectionResult {
+            searched: coarse,
+            found,
+            dl_spec,
+            unrefined: true,
+        });
+    }
+
+    eprintln!("testing the commits in the gap");
+    let found = search(cfg, &gap, |t| install_and_test(t, cfg, client, &dl_spec))?;
+    Ok(BisectionResult {
+        searched: gap,
+        found,
+        dl_spec,
+        unrefined: false,
+    })
+}
+
+/// The indices of the toolchains that aren't rollups, always including the
+/// first and the last one, as they were asked for explicitly.
+fn non_rollup_indices(cfg: &Config, toolchains: &[Toolchain]) -> Vec<usize> {
+    let messages = cfg.commit_messages.borrow();
+    let rollup = |t: &Toolchain| match t.spec {
+        ToolchainSpec::Ci { ref commit, .. } => messages.get(commit).map_or(false, |m| is_rollup(m)),
+        _ => false,
+    };
+    let last = toolchains.len().saturating_sub(1);
+    (0..toolchains.len())
+        .filter(|&i| i == 0 || i == last || !rollup(&toolchains[i]))
+        .collect()
+}
+
+/// For `--bisect-include-rollups=false`: bisects the toolchains at `indices`,
+/// then, if the regression was found right after rollups that were skipped,
+/// the commits in that gap including the rollups.
+fn bisect_ci_skipping_rollups(
+    cfg: &Config,
+    client: &Client,
+    toolchains: Vec<Toolchain>,
+    indices: Vec<usize>,
+    dl_spec: DownloadParams,
+) -> Result<BisectionResult, Error> {
+    let commits = indices
+        .iter()
+        .map(|&i| toolchains[i].clone())
+        .collect::<Vec<_>>();
+    confirm_download(cfg, client, &commits, &dl_spec)?;
+    eprintln!(
+        "testing commits, skipping {} rollups of the {}",
+        toolchains.len() - commits.len(),
+        toolchains.len()
+    );
+    let found = search(cfg, &commits, |t| install_and_test(t, cfg, client, &dl_spec))?;
+    if found == 0 || indices[found] - indices[found - 1] == 1 {
+        return Ok(BisectionResult {
+            searched: commits,
+            found,
+            dl_spec,
+            unrefined: false,
+        });
+    }
+
+    let gap = toolchains[indices[found - 1]..=indices[found]].to_vec();
+    eprintln!(
+        "the regression may be in a rollup between {} and {}, testing those commits too",
+        gap[0],
+        gap[gap.len() - 1]
+    );
+    let found = search(cfg, &gap, |t| install_and_test(t, cfg, client, &dl_spec))?;
+    Ok(BisectionResult {
+        searched: gap,
+        found,
+        dl_spec,
+        unrefined: false,
+    })
+}
+
+/// The indices of every `interval`th of `len` items, always including the
+/// first and the last one.
+fn coarse_indices(len: usize, interval: usize) -> Vec<usize> {
+    let mut indices = (0..len).step_by(interval).collect::<Vec<_>>();
+    if len > 0 && indices.last() != Some(&(len - 1)) {
+        indices.push(len - 1);
+    }
+    indices
+}
+
+#[test]
+fn test_coarse_indices() {
+    assert_eq!(coarse_indices(10, 3), vec![0, 3, 6, 9]);
+    assert_eq!(coarse_indices(11, 3), vec![0, 3, 6, 9, 10]);
+    assert_eq!(coarse_indices(3, 1), vec![0, 1, 2]);
+    assert_eq!(coarse_indices(2, 5), vec![0, 1]);
+}
+
+/// The toolchains of the CI builds between two commits that are still
+/// available for download.
+fn ci_toolchains_between(cfg: &Config, start: &str, end: &str) -> Result<Vec<Toolchain>, Error> {
+    let mut commits = get_commits(start, end, &cfg.args.branch)?;
+    let found = commits.len();
+    let max_age = cfg.args.max_artifact_age;
+    let too_old = format!(
+        "older than {} days, so their artifacts are no longer available \
+         (see --max-artifact-age)",
+        max_age
+    );
+    // each filter, with how many commits it removed
+    let mut filtered: Vec<(&str, usize)> = Vec::new();
+
+    let now = chrono::Utc::now();
+    let before = commits.len();
+    commits.retain(|c| now.signed_duration_since(c.date).num_days() < max_age);
+    if before > commits.len() {
+        eprintln!(
+            "leaving out {} of the {} commits, {}",
+            before - commits.len(),
+            before,
+            too_old
         );
     }
+    filtered.push((&too_old, before - commits.len()));
+
+    if !cfg.args.exclude_commits.is_empty() {
+        let excluded = |sha: &str| {
+            cfg.args
+                .exclude_commits
+                .iter()
+                .any(|prefix| sha.starts_with(prefix.as_str()))
+        };
+        for prefix in &cfg.args.exclude_commits {
+            if !commits.iter().any(|c| c.sha.starts_with(prefix.as_str())) {
+                eprintln!("warning: --exclude-commit {} matches no commit in the range", prefix);
+            }
+        }
+        // the ends of the range stay, as they were asked for explicitly
+        let last = commits.len().saturating_sub(1);
+        let before = commits.len();
+        let mut i = 0;
+        commits.retain(|c| {
+            let keep = i == 0 || i == last || !excluded(&c.sha);
+            if keep && excluded(&c.sha) {
+                eprintln!("warning: not excluding {}, it is an end of the range", c.sha);
+            }
+            i += 1;
+            keep
+        });
+        filtered.push(("excluded with --exclude-commit", before - commits.len()));
+    }
+
+    check_filtered_range(start, end, found, commits.len(), &filtered)?;
 
     if let Some(ref c) = commits.last() {
-        if end != "origin/master" && !c.sha.starts_with(end) {
+        if end != cfg.default_end_ref() && !c.sha.starts_with(end) {
             bail!("expected to end with {}, but ended with {}", end, c.sha);
         }
     }
 
     eprintln!("validated commits found, specifying toolchains");
 
+    cfg.commit_messages.borrow_mut().extend(
+        commits
+            .iter()
+            .map(|commit| (commit.sha.clone(), commit.message.clone())),
+    );
+
     let toolchains = commits
         .into_iter()
         .map(|commit| {
-            let mut t = Toolchain {
-                spec: ToolchainSpec::Ci {
-                    commit: commit.sha.clone(),
-                    alt: cfg.args.alt,
-                },
+            let t = Toolchain {
+                spec: cfg.ci_spec(commit.sha.clone()),
                 host: cfg.args.host.clone(),
-                std_targets: vec![cfg.args.host.clone(), cfg.target.clone()],
+                name_prefix: cfg.args.toolchain_link_name_prefix.clone(),
+                std_targets: cfg.std_targets(),
             };
-            t.std_targets.sort();
-            t.std_targets.dedup();
             t
         })
         .collect::<Vec<_>>();
 
-    eprintln!("testing commits");
-    let found = least_satisfying(&toolchains, |t| {
-        eprintln!("installing {}", t);
-        match t.install(&client, &dl_spec) {
-            Ok(()) => {
-                eprintln!("testing {}", t);
-                let outcome = t.test(&cfg);
-                // we want to fail, so a successful build doesn't satisfy us
-                let r = match outcome {
-                    TestOutcome::Regressed => Satisfies::Yes,
-                    TestOutcome::Baseline => Satisfies::No,
-                };
-                eprintln!("tested {}, got {}", t, r);
-                if !cfg.args.preserve {
-                    let _ = t.remove(&dl_spec);
-                }
-                r
+    Ok(toolchains)
+}
+
+/// Finds the first toolchain that satisfies `predicate`, either automatically
+/// or, with `--interactive`, guided by the user. Fails with the error that
+/// ended testing toolchains, if any.
+fn search<P>(cfg: &Config, toolchains: &[Toolchain], mut predicate: P) -> Result<usize, Error>
+where
+    P: FnMut(&Toolchain) -> Satisfies,
+{
+    let unknown = cfg.args.unknown;
+    let direction = cfg.args.bisect_direction;
+    let predicate = |t: &Toolchain| {
+        let r = predicate(t);
+        match (r, unknown) {
+            (Satisfies::Unknown, UnknownHandling::TreatAsBaseline) => {
+                eprintln!("treating {} as the baseline (--unknown)", t)
             }
-            Err(err) => {
-                let _ = t.remove(&dl_spec);
-                eprintln!("failed to install {}: {:?}", t, err);
-                Satisfies::Unknown
+            (Satisfies::Unknown, UnknownHandling::TreatAsRegressed) => {
+                eprintln!("treating {} as regressed (--unknown)", t)
             }
+            _ => {}
+        }
+        unknown.resolve(direction, r)
+    };
+    if cfg.args.interactive {
+        if atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stderr) {
+            let found = interactive_least_satisfying(toolchains, predicate);
+            cfg.check_fatal()?;
+            return Ok(found);
         }
+        eprintln!("warning: not running in a terminal, so bisecting without --interactive");
+    }
+    let found = least_satisfying_with_progress(toolchains, predicate, |progress: Progress| {
+        eprintln!(
+            "step {} of ~{} ({} toolchains left between the known good and bad ones)",
+            progress.step, progress.estimated_steps, progress.remaining
+        )
     });
+    cfg.check_fatal()?;
+    Ok(found)
+}
 
-    Ok(BisectionResult {
-        searched: toolchains,
-        found,
-        dl_spec,
-    })
+/// Like `least_satisfying`, but shows the remaining range at each step and
+/// lets the user test the suggested midpoint, pick another toolchain, or stop.
+fn interactive_least_satisfying<P>(toolchains: &[Toolchain], mut predicate: P) -> usize
+where
+    P: FnMut(&Toolchain) -> Satisfies,
+{
+    let mut tested: Vec<Option<Satisfies>> = vec![None; toolchains.len()];
+    let mut rm_no = 0;
+    let mut lm_yes = toolchains.len() - 1;
+
+    for &(idx, expected) in &[(rm_no, Satisfies::No), (lm_yes, Satisfies::Yes)] {
+        let r = predicate(&toolchains[idx]);
+        tested[idx] = Some(r);
+        if r != expected {
+            eprintln!(
+                "warning: expected {} to be {}, got {}; the result will not be meaningful",
+                toolchains[idx], expected, r
+            );
+        }
+    }
+
+    loop {
+        let candidates = (rm_no + 1..lm_yes)
+            .filter(|&i| tested[i].is_none())
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return lm_yes;
+        }
+        let middle = (rm_no + lm_yes) / 2;
+        let suggested = *candidates
+            .iter()
+            .min_by_key(|&&i| cmp::max(i, middle) - cmp::min(i, middle))
+            .unwrap();
+
+        eprintln!(
+            "\nthe regression is after {} and at or before {}, {} untested toolchains in between",
+            toolchains[rm_no],
+            toolchains[lm_yes],
+            candidates.len()
+        );
+        let choices = [
+            format!("test {}, the midpoint", toolchains[suggested]),
+            "pick another toolchain to test".to_string(),
+            format!("stop and report {} as the regression", toolchains[lm_yes]),
+        ];
+        let idx = match Select::new().items(&choices).default(0).interact() {
+            Ok(0) => suggested,
+            Ok(1) => {
+                let names = candidates
+                    .iter()
+                    .map(|&i| toolchains[i].to_string())
+                    .collect::<Vec<_>>();
+                let default = candidates.iter().position(|&i| i == suggested).unwrap();
+                match Select::new().items(&names).default(default).interact() {
+                    Ok(pos) => candidates[pos],
+                    Err(_) => return lm_yes,
+                }
+            }
+            _ => return lm_yes,
+        };
+
+        let r = predicate(&toolchains[idx]);
+        tested[idx] = Some(r);
+        match r {
+            Satisfies::Yes => lm_yes = idx,
+            Satisfies::No => rm_no = idx,
+            Satisfies::Unknown => {}
+        }
+    }
+}
+
+/// Fails with a breakdown of what each filter removed if filtering left too
+/// few commits to bisect: at least the two ends of the range are needed.
+fn check_filtered_range(
+    start: &str,
+    end: &str,
+    found: usize,
+    remaining: usize,
+    filtered: &[(&str, usize)],
+) -> Result<(), Error> {
+    if remaining >= 2 || found < 2 {
+        return Ok(());
+    }
+    let mut msg = format!(
+        "{} of the {} commits between {} and {} are left after filtering, \
+         too few to bisect:",
+        remaining, found, start, end
+    );
+    for &(why, removed) in filtered {
+        if removed > 0 {
+            msg.push_str(&format!("\n  {} removed: {}", removed, why));
+        }
+    }
+    bail!("{}", msg)
+}
+
+/// There is nothing to bisect when the range collapsed to a single
+/// toolchain: when the first nightly a nightly search probes is already
+/// good, or when filtering the range, e.g. of expired CI artifacts, or
+/// narrowing it coarsely leaves one. This tests it, unless that already
+/// happened, and reports it directly.
+fn check_single_toolchain(
+    cfg: &Config,
+    client: &Client,
+    toolchains: &[Toolchain],
+    dl_spec: &DownloadParams,
+) -> Result<(), Error> {
+    if toolchains.len() != 1 {
+        return Ok(());
+    }
+    let t = &toolchains[0];
+    eprintln!("the range collapsed to a single toolchain, {}", t);
+    let result = cfg
+        .stats
+        .result_of(t)
+        .unwrap_or_else(|| install_and_test(t, cfg, client, dl_spec));
+    cfg.check_fatal()?;
+    let verdict = match (result, cfg.args.bisect_direction) {
+        (Satisfies::Yes, BisectDirection::Regression) => "has the regression",
+        (Satisfies::No, BisectDirection::Regression) => "does not have the regression",
+        (Satisfies::Yes, BisectDirection::Fix) => "has the fix",
+        (Satisfies::No, BisectDirection::Fix) => "does not have the fix",
+        (Satisfies::Unknown, _) => "could not be tested",
+    };
+    bail!(
+        "{} {}; there is nothing to bisect. Widen the range with --start and --end.",
+        t,
+        verdict
+    )
 }
 
 #[derive(Clone)]
@@ -1260,6 +7446,9 @@ struct BisectionResult {
     searched: Vec<Toolchain>,
     found: usize,
     dl_spec: DownloadParams,
+    /// Set when the gap a coarse bisection found wasn't searched, so that
+    /// `found` only ends the range of commits the regression is in.
+    unrefined: bool,
 }
 
 fn main() {
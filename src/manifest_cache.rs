@@ -0,0 +1,89 @@
+// Copyright 2018 The Rust Project Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Keep the small files fetched from the release server, like the commit
+//! hash of a nightly, along with their `ETag` and `Last-Modified` headers, so
+//! that later runs can ask for them conditionally and reuse them on a
+//! `304 Not Modified`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure::Error;
+use serde_json::{self, Value};
+
+/// A fetched file and what the server said to identify its version.
+#[derive(Debug, PartialEq)]
+pub struct Cached {
+    pub body: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+fn record_path(dir: &Path, url: &str) -> PathBuf {
+    let name = url
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect::<String>();
+    dir.join("manifests").join(format!("{}.json", name))
+}
+
+/// Records `cached` as the contents of `url`.
+pub fn store(dir: &Path, url: &str, cached: &Cached) -> Result<(), Error> {
+    let record = json!({
+        "url": url,
+        "body": cached.body,
+        "etag": cached.etag,
+        "last_modified": cached.last_modified,
+    });
+    let path = record_path(dir, url);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, serde_json::to_vec(&record)?)?;
+    Ok(())
+}
+
+/// The recorded contents of `url`, if there are any.
+pub fn load(dir: &Path, url: &str) -> Option<Cached> {
+    let contents = fs::read(record_path(dir, url)).ok()?;
+    let record: Value = serde_json::from_slice(&contents).ok()?;
+    // the file name may be shared by URLs that only differ in punctuation
+    if record["url"].as_str() != Some(url) {
+        return None;
+    }
+    let text = |key: &str| record[key].as_str().map(|s| s.to_string());
+    Some(Cached {
+        body: text("body")?,
+        etag: text("etag"),
+        last_modified: text("last_modified"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, store, Cached};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn round_trip() {
+        let dir = env::temp_dir().join(format!("bisect-manifest-cache-{}", std::process::id()));
+        let url = "https://static.rust-lang.org/dist/2019-11-01/channel-rust-nightly-git-commit-hash.txt";
+        let cached = Cached {
+            body: "f39205b5d9d4ed5d6ad03e05a8d7ea3cb1f2a5e3".to_string(),
+            etag: Some("\"abc123\"".to_string()),
+            last_modified: None,
+        };
+        store(&dir, url, &cached).unwrap();
+
+        assert_eq!(load(&dir, url), Some(cached));
+        assert_eq!(load(&dir, "https://static.rust-lang.org/dist/manifests.txt"), None);
+        // same file name, different URL
+        assert_eq!(load(&dir, &url.replace("https://", "https:__")), None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2018 The Rust Project Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Record the output of each test run, so that it can be classified again,
+//! e.g. under another `--regress` mode, without reinstalling the toolchain.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+
+use failure::Error;
+use serde_json::{self, Value};
+
+fn record_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.json", name))
+}
+
+/// Records `output` as the result of running `command` for the toolchain
+/// called `name`.
+pub fn store(dir: &Path, name: &str, command: &str, output: &Output) -> Result<(), Error> {
+    let record = json!({
+        "command": command,
+        "code": output.status.code(),
+        "signal": signal_of(output.status),
+        "stdout": String::from_utf8_lossy(&output.stdout),
+        "stderr": String::from_utf8_lossy(&output.stderr),
+    });
+    fs::create_dir_all(dir)?;
+    fs::write(record_path(dir, name), serde_json::to_vec(&record)?)?;
+    Ok(())
+}
+
+/// The recorded output for the toolchain called `name`, if there is one and
+/// it was produced by the same `command`.
+pub fn load(dir: &Path, name: &str, command: &str) -> Option<Output> {
+    let path = record_path(dir, name);
+    let contents = fs::read(&path).ok()?;
+    let record: Value = match serde_json::from_slice(&contents) {
+        Ok(record) => record,
+        Err(err) => {
+            eprintln!("ignoring {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    if record["command"].as_str() != Some(command) {
+        eprintln!(
+            "ignoring {}: it was recorded with a different test command",
+            path.display()
+        );
+        return None;
+    }
+
+    let code = record["code"].as_i64().map(|c| c as i32);
+    let signal = record["signal"].as_i64().map(|s| s as i32);
+    let text = |key: &str| record[key].as_str().unwrap_or("").as_bytes().to_vec();
+    Some(Output {
+        status: exit_status(code, signal),
+        stdout: text("stdout"),
+        stderr: text("stderr"),
+    })
+}
+
+#[cfg(unix)]
+fn signal_of(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal_of(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+#[cfg(unix)]
+fn exit_status(code: Option<i32>, signal: Option<i32>) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    match (code, signal) {
+        (Some(code), _) => ExitStatus::from_raw((code & 0xff) << 8),
+        (None, Some(signal)) => ExitStatus::from_raw(signal & 0x7f),
+        (None, None) => ExitStatus::from_raw(1 << 8),
+    }
+}
+
+#[cfg(windows)]
+fn exit_status(code: Option<i32>, _signal: Option<i32>) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code.unwrap_or(1) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, store};
+    use std::env;
+    use std::fs;
+    use std::process::Output;
+
+    #[test]
+    fn round_trip() {
+        let dir = env::temp_dir().join(format!("bisect-output-cache-{}", std::process::id()));
+        let output = Output {
+            status: super::exit_status(Some(101), None),
+            stdout: b"out".to_vec(),
+            stderr: b"error: internal compiler error".to_vec(),
+        };
+        store(&dir, "nightly", "cargo build", &output).unwrap();
+
+        let loaded = load(&dir, "nightly", "cargo build").unwrap();
+        assert_eq!(loaded.status.code(), Some(101));
+        assert_eq!(loaded.stdout, output.stdout);
+        assert_eq!(loaded.stderr, output.stderr);
+        assert!(load(&dir, "nightly", "cargo check").is_none());
+        assert!(load(&dir, "other", "cargo build").is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
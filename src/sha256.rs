@@ -0,0 +1,95 @@
+// Copyright 2018 The Rust Project Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! SHA-256, to check downloads against the `.sha256` files published next to
+//! the tarballs.
+
+use std::io::{self, Read};
+
+use sha2::{Digest, Sha256};
+
+/// Hashes everything read through it.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Reads whatever is left, e.g. after the end of an archive, and returns
+    /// the digest of the whole stream as lowercase hex.
+    pub fn finish(mut self) -> io::Result<String> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(format!("{:x}", self.hasher.result()))
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.input(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Parses the contents of a `.sha256` file, `<hex digest>  <file name>`.
+pub fn parse_checksum_file(contents: &str) -> Option<String> {
+    let digest = contents.split_whitespace().next()?;
+    if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(digest.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_checksum_file, HashingReader};
+
+    fn digest(data: &[u8]) -> String {
+        HashingReader::new(data).finish().unwrap()
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(
+            digest(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            digest(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn detects_a_wrong_checksum() {
+        let expected = parse_checksum_file(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  abc.tar.xz\n",
+        )
+        .unwrap();
+        let actual = HashingReader::new(&b"abd"[..]).finish().unwrap();
+        assert_ne!(actual, expected);
+        let actual = HashingReader::new(&b"abc"[..]).finish().unwrap();
+        assert_eq!(actual, expected);
+        assert_eq!(parse_checksum_file("not a checksum"), None);
+    }
+}